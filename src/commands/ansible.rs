@@ -6,10 +6,18 @@
 
 use std::collections::HashMap;
 use std::convert::TryInto;
-use std::io::{Error, Write};
+use std::fs;
+use std::io::{self, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
 use std::process::{Command, ExitStatus, Stdio};
+use serde_json::Value;
 use tempfile::NamedTempFile;
 use log::info;
+use crate::commands::exit_status;
+use crate::commands::executor::Executor;
+use crate::commands::process;
+use crate::commands::results::{HostResult, HostStatus, PlaybookHostStats};
+use crate::utils::output;
 use crate::utils::settings::ClusterSettings;
 
 /// Lists all hosts and groups in the configured inventory file.
@@ -27,11 +35,8 @@ pub fn list_hosts(settings: &ClusterSettings) -> Result<ExitStatus, Error> {
         args
     };
 
-    info!("Executing Ansible command {} {:?}", "ansible-inventory", command_arguments);
-    Command::new("ansible-inventory")
-        .stdin(Stdio::piped())
-        .args(command_arguments)
-        .status()
+    process::run(settings, Command::new("ansible-inventory")
+        .args(command_arguments))
 }
 
 /// Represents an Ansible command "session"
@@ -75,19 +80,15 @@ impl AnsibleCommand {
     }
 
     pub fn new_run_command(command: &str, needs_become: bool, host_pattern: Option<String>, chdir: Option<String>) -> AnsibleCommand {
-        AnsibleCommand::new(&String::new(), needs_become, host_pattern.clone())
-            .with_parameter(command, &String::new())
+        AnsibleCommand::new("", needs_become, host_pattern)
+            .with_parameter(command, "")
             .with_optional_parameter("chdir", &chdir)
     }
 
-    /// Creates a new `AnsibleCommand` instance for updating remore machines.
-    pub fn new_update_command(host_pattern: Option<String>) -> AnsibleCommand {
-        AnsibleCommand::new("apt", true, host_pattern.clone())
-            .with_parameter("update_cache", "yes")
-            .with_parameter("autoremove", "yes")
-            .with_parameter("force_apt_get", "yes")
-            .with_parameter("upgrade", "yes")
-        }
+    /// Name of the Ansible module run by the command.
+    pub fn module(&self) -> &str {
+        if self.command.is_empty() { "command" } else { &self.command }
+    }
 
     pub fn with_parameter(mut self, param_name: &str, param_value: &str) -> Self {
         self.parameters.insert(param_name.to_string(), param_value.to_string());
@@ -96,101 +97,207 @@ impl AnsibleCommand {
 
     pub fn with_optional_parameter(self, param_name: &str, param_value: &Option<String>) -> Self {
         match param_value {
-            Some(v) => self.with_parameter(param_name, &v),
+            Some(v) => self.with_parameter(param_name, v),
             None => self
         }
     }
 
     pub fn run(&self, settings: &ClusterSettings) -> Result<ExitStatus, Error> {
-        let command_arguments = {
-            let mut args: Vec<String> = Vec::new();
-
-            if let Some(v) = get_verbose_arguments_from_settings(settings) {
-                args.push(v);
+        if let Some(format) = settings.output {
+            let (status, results) = self.run_with_results(settings)?;
+            if !settings.dry_run {
+                output::print_records(format, &results)?;
             }
+            return Ok(status);
+        }
 
-            if let Some(v) = &settings.inventory {
-                args.push("--inventory".to_string());
-                args.push(v.clone());
-            }
+        info!("Executing Ansible command {}", self.module());
+        process::run(settings, Command::new("ansible")
+            .args(self.arguments(settings)))
+    }
 
-            if self.needs_become {
-                args.push("-K".to_string());
-                args.push("-b".to_string());
-            }
+    /// Runs the command, capturing the result on each host through Ansible's
+    /// JSON output callback. No results are returned when running with
+    /// `--dry-run`.
+    pub fn run_with_results(&self, settings: &ClusterSettings) -> Result<(ExitStatus, Vec<HostResult>), Error> {
+        let mut command = Command::new("ansible");
+        command
+            .env("ANSIBLE_LOAD_CALLBACK_PLUGINS", "1")
+            .env("ANSIBLE_STDOUT_CALLBACK", "json")
+            .args(self.arguments(settings));
+        if settings.dry_run {
+            return Ok((process::run(settings, &mut command)?, Vec::new()));
+        }
 
-            // Command to run
-            if !self.command.is_empty() {
-                args.push("-m".to_string());
-                args.push(self.command.clone());
-            }
+        info!("Executing Ansible command {}", self.module());
+        let (status, stdout) = process::run_captured(&mut command)?;
 
-            // And now all extra parameters
-            if self.parameters.len() > 0 {
-                let mut action_args = String::new();
-                for param in &self.parameters {
-                    if !param.0.is_empty() && !param.1.is_empty() {
-                        if action_args.is_empty() {
-                            action_args.push_str("-a ");
-                        }
-                        action_args.push_str(&format!("{}=\"{}\" ", param.0, param.1));
-                    } else if !param.0.is_empty() && param.1.is_empty() {
-                        if action_args.is_empty() {
-                            action_args.push_str("-a ");
-                        }
-                        action_args.push_str(&format!("{} ", param.0.to_string()));
-                    }
-                }
+        Ok((status, parse_task_results(&stdout)?))
+    }
+
+    fn arguments(&self, settings: &ClusterSettings) -> Vec<String> {
+        let mut args: Vec<String> = Vec::new();
 
-                if !action_args.is_empty() {
-                    args.push(action_args);
+        if let Some(v) = get_verbose_arguments_from_settings(settings) {
+            args.push(v);
+        }
+
+        if let Some(v) = &settings.inventory {
+            args.push("--inventory".to_string());
+            args.push(v.clone());
+        }
+
+        if self.needs_become {
+            args.push("-K".to_string());
+            args.push("-b".to_string());
+        }
+
+        // Command to run
+        if !self.command.is_empty() {
+            args.push("-m".to_string());
+            args.push(self.command.clone());
+        }
+
+        // And now all extra parameters
+        if !self.parameters.is_empty() {
+            let mut action_args = String::new();
+            for param in &self.parameters {
+                if !param.0.is_empty() && !param.1.is_empty() {
+                    if action_args.is_empty() {
+                        action_args.push_str("-a ");
+                    }
+                    action_args.push_str(&format!("{}=\"{}\" ", param.0, param.1));
+                } else if !param.0.is_empty() && param.1.is_empty() {
+                    if action_args.is_empty() {
+                        action_args.push_str("-a ");
+                    }
+                    action_args.push_str(&format!("{} ", param.0));
                 }
             }
 
-            if let Some(pattern) = &self.host_pattern {
-                args.push(pattern.clone());
-            } else {
-                args.push("all".to_string());
+            if !action_args.is_empty() {
+                args.push(action_args);
             }
+        }
 
-            args
-        };
+        if let Some(pattern) = &self.host_pattern {
+            args.push(pattern.clone());
+        } else {
+            args.push("all".to_string());
+        }
 
-        info!("Executing Ansible command {} {:?}", self.command.clone(), command_arguments);
-        Command::new("ansible")
-            .stdin(Stdio::piped())
-            .args(command_arguments)
-            .status()
+        args
+    }
+}
+
+/// Executor running commands through the `ansible` and `ansible-playbook` tools.
+pub struct AnsibleExecutor;
+
+impl Executor for AnsibleExecutor {
+    fn ping(&self, settings: &ClusterSettings) -> Result<ExitStatus, Error> {
+        AnsibleCommand::new("ping", false, settings.host_pattern.clone())
+            .run(settings)
+    }
+
+    fn run(&self, settings: &ClusterSettings, command: &str, needs_become: bool, chdir: Option<String>) -> Result<ExitStatus, Error> {
+        AnsibleCommand::new_run_command(command, needs_become, settings.host_pattern.clone(), chdir)
+            .run(settings)
+    }
+
+    fn copy(&self, settings: &ClusterSettings, src: &str, dest: &str) -> Result<ExitStatus, Error> {
+        AnsibleCommand::new_copy_command(false, settings.host_pattern.clone(), src, dest)
+            .run(settings)
+    }
+
+    fn fetch(&self, settings: &ClusterSettings, src: &str, dest: &str) -> Result<ExitStatus, Error> {
+        AnsibleCommand::new_fetch_command(false, settings.host_pattern.clone(), src, dest)
+            .run(settings)
+    }
+
+    fn run_module(&self, settings: &ClusterSettings, command: &AnsibleCommand) -> Result<ExitStatus, Error> {
+        command.run(settings)
+    }
+
+    fn run_module_with_results(&self, settings: &ClusterSettings, command: &AnsibleCommand) -> Result<(ExitStatus, Vec<HostResult>), Error> {
+        command.run_with_results(settings)
+    }
+
+    fn run_playbook(&self, settings: &ClusterSettings, playbook: &AnsibleAggregatePlaybook) -> Result<ExitStatus, Error> {
+        playbook.run(settings)
+    }
+
+    fn run_playbook_with_results(&self, settings: &ClusterSettings, playbook: &AnsibleAggregatePlaybook) -> Result<(ExitStatus, Vec<HostResult>), Error> {
+        playbook.run_with_results(settings)
     }
 }
 
 /// Represents a single Ansible playbook
+#[derive(Clone)]
 pub struct AnsiblePlaybook {
-    file_contents: String
+    file_contents: String,
+    file_path: Option<PathBuf>
 }
 
 impl AnsiblePlaybook {
     #[cfg(test)]
     pub fn get_available_playbooks() -> Vec<AnsiblePlaybook> {
-        use crate::commands::{INSTALL_DOCKER_COMMAND_PLAYBOOK, INSTALL_KUBERNETES_COMMAND_PLAYBOOK, SETUP_KUBERNETES_CLUSTER_COMMAND_PLAYBOOK, SHOW_HOSTS_DETAILS_COMMAND_PLAYBOOK, UNINSTALL_DOCKER_COMMAND_PLAYBOOK, UNINSTALL_KUBERNETES_COMMAND_PLAYBOOK};
+        use crate::commands::{INSTALL_DOCKER_COMMAND_PLAYBOOK, INSTALL_KUBERNETES_COMMAND_PLAYBOOK, SETUP_KUBERNETES_CLUSTER_COMMAND_PLAYBOOK, SHOW_HOSTS_DETAILS_COMMAND_PLAYBOOK, STATUS_DOCKER_COMMAND_PLAYBOOK, STATUS_KUBERNETES_COMMAND_PLAYBOOK, UNINSTALL_DOCKER_COMMAND_PLAYBOOK, UNINSTALL_KUBERNETES_COMMAND_PLAYBOOK, UPGRADE_KUBERNETES_COMMAND_PLAYBOOK, JOIN_KUBERNETES_COMMAND_PLAYBOOK, LEAVE_KUBERNETES_COMMAND_PLAYBOOK, INSTALL_K3S_COMMAND_PLAYBOOK, UNINSTALL_K3S_COMMAND_PLAYBOOK, STATUS_K3S_COMMAND_PLAYBOOK, INSTALL_CONTAINERD_COMMAND_PLAYBOOK, UNINSTALL_CONTAINERD_COMMAND_PLAYBOOK, INSTALL_PODMAN_COMMAND_PLAYBOOK, UNINSTALL_PODMAN_COMMAND_PLAYBOOK, INSTALL_DOCKER_SWARM_COMMAND_PLAYBOOK, UNINSTALL_DOCKER_SWARM_COMMAND_PLAYBOOK, STATUS_DOCKER_SWARM_COMMAND_PLAYBOOK, UPDATE_COMMAND_PLAYBOOK, CHECK_UPDATES_COMMAND_PLAYBOOK, REBOOT_HOSTS_COMMAND_PLAYBOOK, RECORD_SERVICE_COMMAND_PLAYBOOK};
 
         vec![
             AnsiblePlaybook::load(INSTALL_DOCKER_COMMAND_PLAYBOOK),
             AnsiblePlaybook::load(INSTALL_KUBERNETES_COMMAND_PLAYBOOK),
             AnsiblePlaybook::load(SETUP_KUBERNETES_CLUSTER_COMMAND_PLAYBOOK),
             AnsiblePlaybook::load(SHOW_HOSTS_DETAILS_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(STATUS_DOCKER_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(STATUS_KUBERNETES_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(UPGRADE_KUBERNETES_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(JOIN_KUBERNETES_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(LEAVE_KUBERNETES_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(INSTALL_K3S_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(UNINSTALL_K3S_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(STATUS_K3S_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(INSTALL_CONTAINERD_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(UNINSTALL_CONTAINERD_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(INSTALL_PODMAN_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(UNINSTALL_PODMAN_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(INSTALL_DOCKER_SWARM_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(UNINSTALL_DOCKER_SWARM_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(STATUS_DOCKER_SWARM_COMMAND_PLAYBOOK),
             AnsiblePlaybook::load(UNINSTALL_DOCKER_COMMAND_PLAYBOOK),
-            AnsiblePlaybook::load(UNINSTALL_KUBERNETES_COMMAND_PLAYBOOK)
+            AnsiblePlaybook::load(UNINSTALL_KUBERNETES_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(UPDATE_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(CHECK_UPDATES_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(REBOOT_HOSTS_COMMAND_PLAYBOOK),
+            AnsiblePlaybook::load(RECORD_SERVICE_COMMAND_PLAYBOOK)
         ]
     }
 
     pub fn load(file_contents: &str) -> AnsiblePlaybook {
         AnsiblePlaybook {
-            file_contents: String::from(file_contents)
+            file_contents: String::from(file_contents),
+            file_path: None
         }
     }
 
+    /// Loads a playbook from a file, which is used as is when running it, so
+    /// that any file it references is found relative to it.
+    pub fn load_from_file(path: &Path) -> Result<AnsiblePlaybook, Error> {
+        let file_contents = fs::read_to_string(path)
+            .map_err(|e| Error::new(e.kind(), format!("Could not read playbook {}: {}", path.display(), e)))?;
+
+        Ok(AnsiblePlaybook {
+            file_contents,
+            file_path: Some(path.to_path_buf())
+        })
+    }
+
+    /// Returns the path of a file containing the playbook, saving it to a
+    /// temporary file if it wasn't loaded from one.
     pub fn save_to_file(&self) -> String {
+        if let Some(path) = &self.file_path {
+            return path.to_string_lossy().to_string();
+        }
+
         let mut temp_file = NamedTempFile::new()
             .expect("Could not create temp file");
         info!("Writing Ansible playbook to {}", temp_file.path().display());
@@ -203,10 +310,6 @@ impl AnsiblePlaybook {
         path
     }
 
-    pub fn run(&self, settings: &ClusterSettings) -> Result<ExitStatus, Error> {
-        run_ansible_playbook(settings, vec![self])
-    }
-
     #[cfg(test)]
     pub fn check_syntax(&self) -> Result<ExitStatus, Error> {
         let playbook_file = self.save_to_file();
@@ -224,13 +327,15 @@ impl AnsiblePlaybook {
 
 /// Represents a set of Ansible playbooks to be run together
 pub struct AnsibleAggregatePlaybook {
-    playbooks: Vec<AnsiblePlaybook>
+    playbooks: Vec<AnsiblePlaybook>,
+    extra_vars: Vec<String>
 }
 
 impl AnsibleAggregatePlaybook {
     pub fn new() -> AnsibleAggregatePlaybook {
         AnsibleAggregatePlaybook {
-            playbooks: Vec::new()
+            playbooks: Vec::new(),
+            extra_vars: Vec::new()
         }
     }
 
@@ -238,50 +343,225 @@ impl AnsibleAggregatePlaybook {
         self.playbooks.push(playbook);
     }
 
+    /// Adds variables passed to the playbooks with `--extra-vars`, overriding
+    /// any value set in the playbooks or the inventory.
+    pub fn add_extra_vars(&mut self, vars: &serde_json::Map<String, Value>) {
+        if !vars.is_empty() {
+            self.extra_vars.push(Value::Object(vars.clone()).to_string());
+        }
+    }
+
+    /// Adds a YAML or JSON file with variables passed to the playbooks with
+    /// `--extra-vars`.
+    pub fn add_extra_vars_file(&mut self, path: &str) {
+        self.extra_vars.push(format!("@{}", path));
+    }
+
     pub fn run(&self, settings: &ClusterSettings) -> Result<ExitStatus, Error> {
         let mut playbooks: Vec<&AnsiblePlaybook> = Vec::new();
         for playbook in &self.playbooks {
-            playbooks.push(&playbook);
+            playbooks.push(playbook);
         }
-        run_ansible_playbook(settings, playbooks)
+        run_ansible_playbook(settings, playbooks, &self.extra_vars)
+    }
+
+    /// Runs the playbooks, returning the result of every task on each host.
+    pub fn run_with_results(&self, settings: &ClusterSettings) -> Result<(ExitStatus, Vec<HostResult>), Error> {
+        run_ansible_playbook_with_results(settings, self.playbooks.iter().collect(), &self.extra_vars)
     }
 }
 
-fn run_ansible_playbook(settings: &ClusterSettings, playbooks: Vec<&AnsiblePlaybook>) -> Result<ExitStatus, Error> {
-    let command_arguments = {
-        let mut args: Vec<String> = Vec::new();
+fn playbook_arguments(settings: &ClusterSettings, extra_vars: &[String]) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
 
-        if let Some(v) = get_verbose_arguments_from_settings(settings) {
-            args.push(v);
-        }
+    if let Some(v) = get_verbose_arguments_from_settings(settings) {
+        args.push(v);
+    }
 
-        args.push("-K".to_string());
+    args.push("-K".to_string());
 
-        if let Some(v) = &settings.inventory {
-            args.push("--inventory".to_string());
-            args.push(v.clone());
-        }
+    if let Some(v) = &settings.inventory {
+        args.push("--inventory".to_string());
+        args.push(v.clone());
+    }
 
-        for playbook in &playbooks {
-            let file_name = playbook.save_to_file();
-            args.push(file_name);
-        }
+    for vars in extra_vars {
+        args.push("--extra-vars".to_string());
+        args.push(vars.clone());
+    }
 
-        args
-    };
+    args
+}
+
+fn run_ansible_playbook(settings: &ClusterSettings, playbooks: Vec<&AnsiblePlaybook>, extra_vars: &[String]) -> Result<ExitStatus, Error> {
+    let command_arguments = playbook_arguments(settings, extra_vars);
+    let playbook_files: Vec<String> = playbooks.iter()
+        .map(|p| p.save_to_file())
+        .collect();
+
+    if settings.dry_run {
+        return dry_run_ansible_playbook(settings, command_arguments, &playbook_files, &playbooks);
+    }
 
     // Run playbook
     info!("Executing Ansible playbooks");
+    if let Some(format) = settings.output {
+        let (status, stdout) = process::run_captured(Command::new("ansible-playbook")
+            .env("ANSIBLE_STDOUT_CALLBACK", "json")
+            .args(command_arguments)
+            .args(&playbook_files))?;
+        output::print_records(format, &parse_playbook_stats(&stdout)?)?;
+        return Ok(status);
+    }
+
+    process::run(settings, Command::new("ansible-playbook")
+        .args(command_arguments)
+        .args(&playbook_files))
+}
+
+/// Runs playbooks capturing the result of every task on every host through
+/// Ansible's JSON output callback. No results are returned when running with
+/// `--dry-run`.
+fn run_ansible_playbook_with_results(
+    settings: &ClusterSettings,
+    playbooks: Vec<&AnsiblePlaybook>,
+    extra_vars: &[String]) -> Result<(ExitStatus, Vec<HostResult>), Error> {
+    let command_arguments = playbook_arguments(settings, extra_vars);
+    let playbook_files: Vec<String> = playbooks.iter()
+        .map(|p| p.save_to_file())
+        .collect();
+
+    if settings.dry_run {
+        let status = dry_run_ansible_playbook(settings, command_arguments, &playbook_files, &playbooks)?;
+        return Ok((status, Vec::new()));
+    }
+
+    info!("Executing Ansible playbooks");
+    let (status, stdout) = process::run_captured(Command::new("ansible-playbook")
+        .env("ANSIBLE_STDOUT_CALLBACK", "json")
+        .args(command_arguments)
+        .args(&playbook_files))?;
+
+    Ok((status, parse_task_results(&stdout)?))
+}
+
+/// Prints the `ansible-playbook` command line and the playbooks it would run,
+/// optionally running them in check mode to show what they would change.
+fn dry_run_ansible_playbook(
+    settings: &ClusterSettings,
+    command_arguments: Vec<String>,
+    playbook_files: &[String],
+    playbooks: &[&AnsiblePlaybook]) -> Result<ExitStatus, Error> {
+    let mut command = Command::new("ansible-playbook");
+    command
+        .args(&command_arguments)
+        .args(playbook_files);
+    process::print_command(&command)?;
+
+    let mut stdout = io::stdout();
+    for (file, playbook) in playbook_files.iter().zip(playbooks) {
+        writeln!(stdout, "\n# {}\n{}", file, playbook.file_contents.trim_end())?;
+    }
+
+    if !settings.check {
+        return Ok(exit_status(true));
+    }
+
+    info!("Running Ansible playbooks in check mode");
+    writeln!(stdout)?;
     Command::new("ansible-playbook")
         .stdin(Stdio::piped())
+        .args(["--check", "--diff"])
         .args(command_arguments)
+        .args(playbook_files)
         .status()
 }
 
+/// Parses the output of Ansible's JSON callback, skipping any text printed
+/// before the JSON document.
+fn parse_json_output(stdout: &str) -> Result<Value, Error> {
+    let start = stdout.find("\n{").map(|i| i + 1)
+        .or_else(|| if stdout.starts_with('{') { Some(0) } else { None })
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Ansible didn't return any JSON output"))?;
+
+    serde_json::from_str(&stdout[start..])
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Could not parse Ansible output: {}", e)))
+}
+
+/// Extracts the result of every task on every host from Ansible's JSON output.
+fn parse_task_results(stdout: &str) -> Result<Vec<HostResult>, Error> {
+    let json = parse_json_output(stdout)?;
+    let mut results = Vec::new();
+
+    let plays = json["plays"].as_array().cloned().unwrap_or_default();
+    for play in &plays {
+        for task in play["tasks"].as_array().map(Vec::as_slice).unwrap_or_default() {
+            if let Some(hosts) = task["hosts"].as_object() {
+                for (host, result) in hosts {
+                    results.push(host_result_from_json(host, result));
+                }
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+fn host_result_from_json(host: &str, result: &Value) -> HostResult {
+    let flag = |key: &str| result[key].as_bool().unwrap_or(false);
+    let text = |key: &str| match &result[key] {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string()
+    };
+
+    let status = if flag("unreachable") {
+        HostStatus::Unreachable
+    } else if flag("failed") {
+        HostStatus::Failed
+    } else if flag("skipped") {
+        HostStatus::Skipped
+    } else if flag("changed") {
+        HostStatus::Changed
+    } else {
+        HostStatus::Ok
+    };
+
+    HostResult {
+        host: host.to_string(),
+        status,
+        rc: result["rc"].as_i64(),
+        stdout: text("stdout"),
+        stderr: text("stderr"),
+        msg: text("msg"),
+        facts: result["ansible_facts"].clone()
+    }
+}
+
+/// Extracts the per-host summary of a playbook run from Ansible's JSON output.
+fn parse_playbook_stats(stdout: &str) -> Result<Vec<PlaybookHostStats>, Error> {
+    let json = parse_json_output(stdout)?;
+    let stats = json["stats"].as_object().cloned().unwrap_or_default();
+
+    Ok(stats.iter()
+        .map(|(host, s)| {
+            let count = |key: &str| s[key].as_u64().unwrap_or(0);
+            PlaybookHostStats {
+                host: host.clone(),
+                ok: count("ok"),
+                changed: count("changed"),
+                failed: count("failures"),
+                unreachable: count("unreachable"),
+                skipped: count("skipped")
+            }
+        })
+        .collect())
+}
+
 fn get_verbose_arguments_from_settings(settings: &ClusterSettings) -> Option<String> {
     match settings.verbose {
         0 => None,
-        count if count >= 1 && count <= 4 => Some(format!("-{}", str::repeat("v", count.try_into().unwrap()))),
+        count if (1..=4).contains(&count) => Some(format!("-{}", str::repeat("v", count.try_into().unwrap()))),
         _ => None
     }
 }
@@ -290,7 +570,35 @@ fn get_verbose_arguments_from_settings(settings: &ClusterSettings) -> Option<Str
 mod tests {
     use std::{fs, process::ExitStatus};
     use rstest::rstest;
-    use super::{AnsibleCommand, AnsiblePlaybook};
+    use serde_yaml::{Mapping, Value};
+    use crate::commands::results::{HostStatus, PendingUpdate, ServiceHealth, UpdateResult};
+    use crate::commands::services::ServiceRegistry;
+    use super::{AnsibleCommand, AnsiblePlaybook, parse_playbook_stats, parse_task_results};
+
+    const JSON_OUTPUT: &str = r#"[WARNING]: Platform linux on host worker1 is using the discovered Python interpreter
+{
+    "custom_stats": {},
+    "plays": [
+        {
+            "play": {"name": "Ansible Ad-Hoc"},
+            "tasks": [
+                {
+                    "hosts": {
+                        "master1": {"changed": true, "rc": 0, "stdout": " 10:00:00 up 3 days", "stderr": ""},
+                        "worker1": {"changed": false, "msg": "Failed to connect to the host via ssh", "unreachable": true},
+                        "worker2": {"changed": true, "failed": true, "rc": 2, "stdout": "", "stderr": "ls: cannot access"}
+                    },
+                    "task": {"name": "command"}
+                }
+            ]
+        }
+    ],
+    "stats": {
+        "master1": {"changed": 1, "failures": 0, "ok": 1, "skipped": 0, "unreachable": 0},
+        "worker1": {"changed": 0, "failures": 0, "ok": 0, "skipped": 0, "unreachable": 1}
+    }
+}
+"#;
 
     #[rstest]
     fn playbook_is_correctly_saved() {
@@ -301,6 +609,23 @@ mod tests {
         }
     }
 
+    #[rstest]
+    fn playbooks_do_not_override_service_variables() {
+        let variables: Vec<String> = ServiceRegistry::with_builtin_services().services().iter()
+            .flat_map(|s| s.variables())
+            .map(|v| v.name)
+            .collect();
+
+        for playbook in AnsiblePlaybook::get_available_playbooks() {
+            let plays: Vec<Mapping> = serde_yaml::from_str(&playbook.file_contents).unwrap();
+            for vars in plays.iter().filter_map(|p| p.get(&Value::from("vars")).and_then(|v| v.as_mapping())) {
+                for variable in &variables {
+                    assert!(!vars.contains_key(&Value::from(variable.as_str())), "'{}' is set in the play vars", variable);
+                }
+            }
+        }
+    }
+
     #[rstest]
     fn playbooks_syntax_is_correct() {
         for playbook in AnsiblePlaybook::get_available_playbooks() {
@@ -334,4 +659,137 @@ mod tests {
             assert!(!command.parameters.contains_key("opt_param1"));
         }
     }
-}
\ No newline at end of file
+
+    #[rstest]
+    fn task_results_are_correctly_parsed() {
+        let results = parse_task_results(JSON_OUTPUT).unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].host, "master1");
+        assert_eq!(results[0].status, HostStatus::Changed);
+        assert_eq!(results[0].output(), " 10:00:00 up 3 days");
+        assert_eq!(results[1].status, HostStatus::Unreachable);
+        assert_eq!(results[1].output(), "Failed to connect to the host via ssh");
+        assert_eq!(results[2].status, HostStatus::Failed);
+        assert_eq!(results[2].rc, Some(2));
+        assert_eq!(results[2].output(), "ls: cannot access");
+    }
+
+    #[rstest]
+    fn playbook_stats_are_correctly_parsed() {
+        let stats = parse_playbook_stats(JSON_OUTPUT).unwrap();
+
+        assert_eq!(stats.len(), 2);
+        assert_eq!((stats[0].host.as_str(), stats[0].ok, stats[0].changed), ("master1", 1, 1));
+        assert_eq!((stats[1].host.as_str(), stats[1].unreachable), ("worker1", 1));
+    }
+
+    #[rstest]
+    fn service_health_is_extracted_from_status_playbooks() {
+        let stdout = r#"{
+    "plays": [
+        {
+            "tasks": [
+                {
+                    "hosts": {
+                        "master1": {"changed": false},
+                        "worker1": {"changed": false, "unreachable": true, "msg": "Connection timed out"},
+                        "worker2": {"changed": false}
+                    }
+                },
+                {
+                    "hosts": {
+                        "master1": {"changed": false, "ansible_facts": {"service_status": [
+                            {"component": "docker-ce", "state": "5:20.10.7~3-0~ubuntu-focal", "healthy": true},
+                            {"component": "docker.service", "state": "stopped", "healthy": "False"}
+                        ]}},
+                        "worker2": {"changed": false, "failed": true, "msg": "Assertion failed"}
+                    }
+                }
+            ]
+        }
+    ]
+}"#;
+        let results = parse_task_results(stdout).unwrap();
+        let health: Vec<(String, String, String, bool)> = ServiceHealth::from_results("docker", &results).into_iter()
+            .map(|h| (h.host, h.component, h.state, h.healthy))
+            .collect();
+
+        assert_eq!(health, vec![
+            ("master1".to_string(), "docker-ce".to_string(), "5:20.10.7~3-0~ubuntu-focal".to_string(), true),
+            ("master1".to_string(), "docker.service".to_string(), "stopped".to_string(), false),
+            ("worker1".to_string(), "playbook".to_string(), "unreachable".to_string(), false),
+            ("worker2".to_string(), "playbook".to_string(), "failed".to_string(), false)
+        ]);
+    }
+
+    #[rstest]
+    fn pending_updates_are_extracted_from_check_playbook() {
+        let stdout = r#"{
+    "plays": [
+        {
+            "tasks": [
+                {
+                    "hosts": {
+                        "master1": {"changed": false, "ansible_facts": {"pending_updates": [
+                            {"package": "openssl", "current": "1.1.1f-1ubuntu2.4", "candidate": "1.1.1f-1ubuntu2.5", "security": "yes"},
+                            {"package": "vim", "current": "2:8.1.2269-1ubuntu5", "candidate": "2:8.1.2269-1ubuntu5.3", "security": "no"}
+                        ]}},
+                        "worker1": {"changed": false, "ansible_facts": {"pending_updates": []}}
+                    }
+                }
+            ]
+        }
+    ]
+}"#;
+        let results = parse_task_results(stdout).unwrap();
+        let updates: Vec<(String, String, bool)> = PendingUpdate::from_results(&results).into_iter()
+            .map(|u| (u.host, u.package, u.security))
+            .collect();
+
+        assert_eq!(updates, vec![
+            ("master1".to_string(), "openssl".to_string(), true),
+            ("master1".to_string(), "vim".to_string(), false)
+        ]);
+    }
+
+    #[rstest]
+    fn update_results_report_hosts_needing_reboot() {
+        let stdout = r#"{
+    "plays": [
+        {
+            "tasks": [
+                {
+                    "hosts": {
+                        "master1": {"changed": true},
+                        "worker1": {"changed": false},
+                        "worker2": {"changed": false, "failed": true, "msg": "Could not get lock /var/lib/dpkg/lock"}
+                    }
+                },
+                {
+                    "hosts": {
+                        "master1": {"changed": false, "ansible_facts": {"reboot_required": true}},
+                        "worker1": {"changed": false, "ansible_facts": {"reboot_required": "False"}}
+                    }
+                }
+            ]
+        }
+    ]
+}"#;
+        let results = parse_task_results(stdout).unwrap();
+        let updates: Vec<(String, HostStatus, bool)> = UpdateResult::from_results(&results).into_iter()
+            .map(|u| (u.host, u.status, u.reboot_required))
+            .collect();
+
+        assert_eq!(updates, vec![
+            ("master1".to_string(), HostStatus::Changed, true),
+            ("worker1".to_string(), HostStatus::Ok, false),
+            ("worker2".to_string(), HostStatus::Failed, false)
+        ]);
+    }
+
+    #[rstest]
+    fn missing_json_output_is_an_error() {
+        assert!(parse_task_results("ERROR! the playbook could not be found").is_err());
+    }
+}
@@ -0,0 +1,66 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::io::Error;
+use std::process::ExitStatus;
+
+use log::error;
+
+use crate::commands::ansible::{AnsibleAggregatePlaybook, AnsibleCommand, AnsibleExecutor};
+use crate::commands::results::HostResult;
+use crate::commands::ssh::SshExecutor;
+use crate::utils::inventory::Inventory;
+use crate::utils::settings::{Backend, ClusterSettings};
+
+/// Runs commands on the machines matched by the host pattern in the settings.
+pub trait Executor {
+    fn ping(&self, settings: &ClusterSettings) -> Result<ExitStatus, Error>;
+
+    fn run(&self, settings: &ClusterSettings, command: &str, needs_become: bool, chdir: Option<String>) -> Result<ExitStatus, Error>;
+
+    fn uptime(&self, settings: &ClusterSettings) -> Result<ExitStatus, Error> {
+        self.run(settings, "uptime", false, None)
+    }
+
+    /// Copies a local file to the machines.
+    fn copy(&self, settings: &ClusterSettings, src: &str, dest: &str) -> Result<ExitStatus, Error>;
+
+    /// Fetches a file from the machines into `dest/<host>/<src>`.
+    fn fetch(&self, settings: &ClusterSettings, src: &str, dest: &str) -> Result<ExitStatus, Error>;
+
+    /// Runs an Ansible module on the machines.
+    fn run_module(&self, _settings: &ClusterSettings, command: &AnsibleCommand) -> Result<ExitStatus, Error> {
+        Err(unsupported(&format!("Ansible module '{}'", command.module())))
+    }
+
+    /// Runs an Ansible module on the machines, returning the result on each one.
+    fn run_module_with_results(&self, _settings: &ClusterSettings, command: &AnsibleCommand) -> Result<(ExitStatus, Vec<HostResult>), Error> {
+        Err(unsupported(&format!("Ansible module '{}'", command.module())))
+    }
+
+    fn run_playbook(&self, _settings: &ClusterSettings, _playbook: &AnsibleAggregatePlaybook) -> Result<ExitStatus, Error> {
+        Err(unsupported("Ansible playbooks"))
+    }
+
+    /// Runs Ansible playbooks, returning the result of every task on each machine.
+    fn run_playbook_with_results(&self, _settings: &ClusterSettings, _playbook: &AnsibleAggregatePlaybook) -> Result<(ExitStatus, Vec<HostResult>), Error> {
+        Err(unsupported("Ansible playbooks"))
+    }
+}
+
+/// Creates the executor for the backend selected in the settings.
+pub fn executor(settings: &ClusterSettings) -> Result<Box<dyn Executor>, Error> {
+    match settings.backend {
+        Backend::Ansible => Ok(Box::new(AnsibleExecutor)),
+        Backend::Ssh => Ok(Box::new(SshExecutor::new(Inventory::from_settings(settings)?)))
+    }
+}
+
+fn unsupported(what: &str) -> Error {
+    let msg = format!("{} can't be run with the ssh backend, use '--backend ansible'", what);
+    error!("{}", msg);
+    Error::other(msg)
+}
@@ -4,29 +4,77 @@
  * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
  */
 
+use std::fs;
 use std::include_str;
 use std::io::{Error, ErrorKind};
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
 use std::process::ExitStatus;
 
 use log::{error, info};
 
 mod ansible;
 use crate::commands::ansible::{AnsibleAggregatePlaybook, AnsibleCommand, AnsiblePlaybook};
+mod executor;
+use crate::commands::executor::Executor;
+mod power;
+mod process;
+mod results;
+use crate::commands::results::{AvailableService, InventoryHost, ServiceState};
+mod services;
+use crate::commands::services::{Service, ServiceRegistry, ServiceValues};
+use crate::commands::services::state::InstalledServices;
+mod ssh;
+mod update;
 
+use crate::utils::inventory::{inventory_path, validate, Inventory};
+use crate::utils::inventory::edit::InventoryEditor;
+use crate::utils::inventory::validate::{CLUSTER_GROUP, CLUSTER_MANAGERS_GROUP, CLUSTER_WORKERS_GROUP};
+use crate::utils::{kubeconfig, output};
 use crate::utils::settings::*;
 
 // Command names, which are also playbook file names
 const SHOW_HOSTS_DETAILS_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/show-hosts-details.yaml");
+const UPDATE_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/update-packages.yaml");
+const CHECK_UPDATES_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/check-updates.yaml");
+const REBOOT_HOSTS_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/reboot-hosts.yaml");
+const RECORD_SERVICE_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/record-service.yaml");
 const INSTALL_KUBERNETES_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/install-kubernetes.yaml");
 const UNINSTALL_KUBERNETES_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/uninstall-kubernetes.yaml");
 const SETUP_KUBERNETES_CLUSTER_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/setup-kubernetes-cluster.yaml");
+const STATUS_KUBERNETES_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/status-kubernetes.yaml");
+const UPGRADE_KUBERNETES_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/upgrade-kubernetes.yaml");
+const JOIN_KUBERNETES_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/join-kubernetes.yaml");
+const LEAVE_KUBERNETES_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/leave-kubernetes.yaml");
+
+const INSTALL_K3S_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/install-k3s.yaml");
+const UNINSTALL_K3S_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/uninstall-k3s.yaml");
+const STATUS_K3S_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/status-k3s.yaml");
+
+const INSTALL_DOCKER_SWARM_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/install-docker-swarm.yaml");
+const UNINSTALL_DOCKER_SWARM_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/uninstall-docker-swarm.yaml");
+const STATUS_DOCKER_SWARM_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/status-docker-swarm.yaml");
+
+const INSTALL_CONTAINERD_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/install-containerd.yaml");
+const UNINSTALL_CONTAINERD_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/uninstall-containerd.yaml");
+
+const INSTALL_PODMAN_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/install-podman.yaml");
+const UNINSTALL_PODMAN_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/uninstall-podman.yaml");
 
 const INSTALL_DOCKER_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/install-docker.yaml");
 const UNINSTALL_DOCKER_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/uninstall-docker.yaml");
+const STATUS_DOCKER_COMMAND_PLAYBOOK: &str = include_str!("../../playbooks/status-docker.yaml");
+
+// Kubeconfigs generated on the masters by kubeadm and k3s
+const ADMIN_KUBECONFIG_PATHS: [&str; 2] = ["/etc/kubernetes/admin.conf", "/etc/rancher/k3s/k3s.yaml"];
 
 // Service names
+const SERVICE_NAME_CONTAINERD: &str = "containerd";
 const SERVICE_NAME_DOCKER: &str = "docker";
+const SERVICE_NAME_DOCKER_SWARM: &str = "docker-swarm";
+const SERVICE_NAME_PODMAN: &str = "podman";
 const SERVICE_NAME_KUBERNETES: &str = "kubernetes";
+const SERVICE_NAME_K3S: &str = "k3s";
 
 pub trait CommandRunner {
     fn run(&self) -> Result<ExitStatus, Error>;
@@ -45,108 +93,333 @@ impl CommandRunner for ClusterSettings {
         log::set_max_level(log_level);
 
         match self.subcommand {
-            SubCommand::Copy(ref cc) => {
-                AnsibleCommand::new_copy_command(false, self.host_pattern.clone(), cc.src.as_str(), cc.dest.as_str())
-                    .run(self)
-            },
+            SubCommand::Copy(ref cc) => executor::executor(self)?.copy(self, &cc.src, &cc.dest),
 
-            SubCommand::Fetch(ref cc) => {
-                AnsibleCommand::new_fetch_command(false, self.host_pattern.clone(), cc.src.as_str(), cc.dest.as_str())
-                    .run(self)
-            },
+            SubCommand::Fetch(ref cc) => executor::executor(self)?.fetch(self, &cc.src, &cc.dest),
 
             SubCommand::Inventory(ref ic) => {
-                match &ic.subcommand {
-                    &InventorySubCommand::List(ref _options) => {
-                        ansible::list_hosts(self)
+                match ic.subcommand {
+                    InventorySubCommand::List(ref _options) => {
+                        match self.output {
+                            Some(format) => run_list_inventory_hosts(self, format),
+                            None => ansible::list_hosts(self)
+                        }
                     },
-                    &InventorySubCommand::Show(ref _options) => {
-                        AnsiblePlaybook::load(SHOW_HOSTS_DETAILS_COMMAND_PLAYBOOK)
-                            .run(self)
+                    InventorySubCommand::Show(ref _options) => {
+                        let mut playbook = AnsibleAggregatePlaybook::new();
+                        playbook.add_playbook(AnsiblePlaybook::load(SHOW_HOSTS_DETAILS_COMMAND_PLAYBOOK));
+                        executor::executor(self)?.run_playbook(self, &playbook)
+                    },
+                    InventorySubCommand::Validate(ref _options) => run_validate_inventory(self),
+                    InventorySubCommand::AddHost(ref options) => {
+                        run_edit_inventory(self, |editor, inventory| {
+                            let mut groups = options.groups.clone();
+                            if groups.is_empty() {
+                                groups.push(CLUSTER_WORKERS_GROUP.to_string());
+                            }
+                            if !groups.iter().any(|g| g == CLUSTER_GROUP || inventory.is_child_group(CLUSTER_GROUP, g)) {
+                                groups.insert(0, CLUSTER_GROUP.to_string());
+                            }
+
+                            info!("Adding host '{}' to groups {:?}", options.host, groups);
+                            editor.add_host(&options.host, &options.address, &groups)
+                        })
+                    },
+                    InventorySubCommand::RemoveHost(ref options) => {
+                        run_edit_inventory(self, |editor, _inventory| {
+                            info!("Removing host '{}'", options.host);
+                            editor.remove_host(&options.host)
+                        })
+                    },
+                    InventorySubCommand::MoveHost(ref options) => {
+                        run_edit_inventory(self, |editor, _inventory| {
+                            let (from, to) = match options.to {
+                                ClusterRole::Managers => (CLUSTER_WORKERS_GROUP, CLUSTER_MANAGERS_GROUP),
+                                ClusterRole::Workers => (CLUSTER_MANAGERS_GROUP, CLUSTER_WORKERS_GROUP)
+                            };
+
+                            info!("Moving host '{}' from '{}' to '{}'", options.host, from, to);
+                            editor.move_host(&options.host, from, to)
+                        })
                     }
                 }
             },
 
-            SubCommand::Ping(ref _gc) => {
-                AnsibleCommand::new("ping", false, self.host_pattern.clone())
-                    .run(self)
-            },
+            SubCommand::Kubeconfig(ref kc) => run_kubeconfig(self, kc),
 
-            SubCommand::Reboot(ref _gc) => {
-                AnsibleCommand::new("reboot", true, self.host_pattern.clone())
-                    .run(self)
-            },
+            SubCommand::Ping(ref _gc) => executor::executor(self)?.ping(self),
+
+            SubCommand::Poweron(ref pc) => power::run_poweron(self, pc),
+
+            SubCommand::Reboot(ref rc) => power::run_reboot(self, rc),
 
             SubCommand::Run(ref rc) => {
-                AnsibleCommand::new_run_command(&rc.command, rc.needs_become, self.host_pattern.clone(), rc.chdir.clone())
-                    .run(self)
+                executor::executor(self)?
+                    .run(self, &rc.command, rc.needs_become, rc.chdir.clone())
             },
 
             SubCommand::Service(ref sc) => {
                 match &sc.subcommand {
-                    ServiceSubCommand::Deploy(ref options) => run_deploy_service(self, sc, options),
-                    ServiceSubCommand::Delete(ref options) => run_delete_service(self, sc, options),
-                    &ServiceSubCommand::List(ref _options) => {
-                        AnsibleCommand::new("service_facts", false, self.host_pattern.clone())
-                            .run(self)
+                    ServiceSubCommand::Deploy(ref options) => run_deploy_service(self, options),
+                    ServiceSubCommand::Delete(ref options) => run_delete_service(self, options),
+                    ServiceSubCommand::Status(ref options) => {
+                        run_service_operation(self, options, |service, executor, values| {
+                            info!("Checking status of service '{}'", service.name());
+                            service.status(self, executor, values)
+                        })
+                    },
+                    ServiceSubCommand::Upgrade(ref options) => {
+                        run_service_operation(self, &options.service_options, |service, executor, values| {
+                            info!("Upgrading service '{}'", service.name());
+                            service.upgrade(self, executor, &values.with_version(options.version.as_deref()))
+                        })
+                    },
+                    ServiceSubCommand::Join(ref options) => {
+                        run_service_operation(self, options, |service, executor, values| {
+                            info!("Adding hosts to service '{}'", service.name());
+                            service.join(self, executor, &values.with_target_hosts(self.host_pattern.as_deref(), "join")?)
+                        })
+                    },
+                    ServiceSubCommand::Leave(ref options) => {
+                        run_service_operation(self, options, |service, executor, values| {
+                            info!("Removing hosts from service '{}'", service.name());
+                            service.leave(self, executor, &values.with_target_hosts(self.host_pattern.as_deref(), "leave")?)
+                        })
+                    },
+                    ServiceSubCommand::ListAvailable(ref _options) => run_list_available_services(self),
+                    ServiceSubCommand::List(ref _options) => {
+                        let executor = executor::executor(self)?;
+                        let command = AnsibleCommand::new("service_facts", false, self.host_pattern.clone());
+                        match self.output {
+                            Some(format) => {
+                                let (status, results) = executor.run_module_with_results(self, &command)?;
+                                if !self.dry_run {
+                                    output::print_records(format, &ServiceState::from_results(&results))?;
+                                }
+                                Ok(status)
+                            },
+                            None => executor.run_module(self, &command)
+                        }
                     }
                 }
             },
 
-            SubCommand::Shutdown(ref _gc) => {
-                AnsibleCommand::new("community.general.shutdown", true, self.host_pattern.clone())
-                    .run(self)
-            },
+            SubCommand::Shutdown(ref sc) => power::run_shutdown(self, sc),
 
-            SubCommand::Ssh(ref _sc) => {
-                AnsibleCommand::new("ssh", false, self.host_pattern.clone())
-                    .run(self)
-            },
+            SubCommand::Ssh(ref sc) => ssh::run_interactive_ssh(self, sc.host.as_deref()),
 
-            SubCommand::Update(ref _gc) => {
-                AnsibleCommand::new_update_command(self.host_pattern.clone())
-                    .run(self)
-            },
+            SubCommand::Update(ref uc) => update::run_update(self, uc),
 
-            SubCommand::Uptime(ref _uc) => {
-                AnsibleCommand::new_run_command("uptime", false, self.host_pattern.clone(), Option::<String>::None)
-                    .run(self)
-            }
+            SubCommand::Uptime(ref _uc) => executor::executor(self)?.uptime(self)
         }
     }
 }
 
-fn run_deploy_service(settings: &ClusterSettings, _sc: &ServiceCommand, options: &ServiceCommandOptions) -> Result<ExitStatus, Error> {
-    let mut playbook = AnsibleAggregatePlaybook::new();
+/// Fetches the admin kubeconfig from the main master, and saves it to be used
+/// from the local machine.
+fn run_kubeconfig(settings: &ClusterSettings, options: &KubeconfigCommand) -> Result<ExitStatus, Error> {
+    let inventory = Inventory::from_settings(settings)?;
+    let master = match inventory.group_hosts(CLUSTER_MANAGERS_GROUP).first() {
+        Some(h) => h.name.clone(),
+        None => {
+            let msg = format!("There are no hosts in the '{}' group to get the kubeconfig from", CLUSTER_MANAGERS_GROUP);
+            error!("{}", msg);
+            return Err(Error::new(ErrorKind::NotFound, msg));
+        }
+    };
+    let address = inventory.host_var(&master, "ansible_host").unwrap_or_else(|| master.clone());
+    let endpoints: Vec<String> = ["control_plane_vip", "control_plane_endpoint"].iter()
+        .filter_map(|v| inventory.host_var(&master, v))
+        .collect();
 
-    info!("Deploying service '{}' to cluster", &options.service);
-    if options.service == SERVICE_NAME_KUBERNETES {
-        playbook.add_playbook(AnsiblePlaybook::load(INSTALL_KUBERNETES_COMMAND_PLAYBOOK));
-        playbook.add_playbook(AnsiblePlaybook::load(SETUP_KUBERNETES_CLUSTER_COMMAND_PLAYBOOK));
-    } else if options.service == SERVICE_NAME_DOCKER {
-        playbook.add_playbook(AnsiblePlaybook::load(INSTALL_DOCKER_COMMAND_PLAYBOOK));
-    } else {
-        let msg = format!("Unknown service '{}', can't deploy", options.service);
-        error!("{}", msg);
-        return Err(Error::new(ErrorKind::Other, msg));
+    info!("Fetching kubeconfig from '{}'", master);
+    let read_kubeconfig: Vec<String> = ADMIN_KUBECONFIG_PATHS.iter()
+        .map(|p| format!("cat {} 2>/dev/null", p))
+        .collect();
+    let command = AnsibleCommand::new("shell", true, Some(master.clone()))
+        .with_parameter(&read_kubeconfig.join(" || "), "");
+    let (status, results) = executor::executor(settings)?.run_module_with_results(settings, &command)?;
+    if settings.dry_run {
+        return Ok(status);
     }
 
-    playbook.run(settings)
+    let admin_conf = match results.iter().find(|r| r.host == master) {
+        Some(r) if r.is_success() => r.stdout.clone(),
+        result => {
+            let msg = format!("Could not read the kubeconfig on '{}', is Kubernetes deployed? {}", master, result.map(|r| r.output()).unwrap_or_default());
+            error!("{}", msg);
+            return Err(Error::other(msg));
+        }
+    };
+    let config = kubeconfig::rewrite(&admin_conf, &address, &endpoints, &options.context)?;
+
+    let path = match &options.file {
+        Some(file) => {
+            kubeconfig::save(&PathBuf::from(file), &config)?;
+            PathBuf::from(file)
+        },
+        None => {
+            let path = kubeconfig::default_path()?;
+            let existing = match fs::read_to_string(&path) {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(e)
+            };
+            kubeconfig::save(&path, &kubeconfig::merge(&existing, &config)?)?;
+            path
+        }
+    };
+
+    println!("Added context '{}' to {}", options.context, path.display());
+    Ok(exit_status(true))
 }
 
-fn run_delete_service(settings: &ClusterSettings, _sc: &ServiceCommand, options: &ServiceCommandOptions) -> Result<ExitStatus, Error> {
-    let mut playbook = AnsibleAggregatePlaybook::new();
+fn run_list_inventory_hosts(settings: &ClusterSettings, format: OutputFormat) -> Result<ExitStatus, Error> {
+    let inventory = Inventory::from_settings(settings)?;
+    let hosts: Vec<InventoryHost> = inventory.hosts().iter()
+        .map(|h| InventoryHost {
+            host: h.name.clone(),
+            address: inventory.host_var(&h.name, "ansible_host").unwrap_or_default(),
+            groups: inventory.host_groups(&h.name).iter()
+                .filter(|g| g.has_host(&h.name))
+                .map(|g| g.name.clone())
+                .collect()
+        })
+        .collect();
 
-    info!("Deleting service '{}' from cluster", &options.service);
-    if options.service == SERVICE_NAME_KUBERNETES {
-        playbook.add_playbook(AnsiblePlaybook::load(UNINSTALL_KUBERNETES_COMMAND_PLAYBOOK));
-    } else if options.service == SERVICE_NAME_DOCKER {
-        playbook.add_playbook(AnsiblePlaybook::load(UNINSTALL_DOCKER_COMMAND_PLAYBOOK));
+    output::print_records(format, &hosts)?;
+    Ok(exit_status(true))
+}
+
+fn run_validate_inventory(settings: &ClusterSettings) -> Result<ExitStatus, Error> {
+    let inventory = Inventory::from_settings(settings)?;
+
+    info!("Validating inventory");
+    let issues = validate::validate(&inventory);
+    for issue in &issues {
+        println!("{}", issue);
+    }
+
+    if issues.is_empty() {
+        println!("Inventory is valid");
     } else {
-        let msg = format!("Unknown service '{}', can't deploy", options.service);
+        println!("Found {} problem(s) in the inventory", issues.len());
+    }
+
+    Ok(exit_status(issues.is_empty()))
+}
+
+/// Applies a change to the inventory file, refusing to save it if it would
+/// introduce new validation problems.
+fn run_edit_inventory<F>(settings: &ClusterSettings, edit: F) -> Result<ExitStatus, Error>
+    where F: FnOnce(&mut InventoryEditor, &Inventory) -> Result<(), Error> {
+    let mut editor = InventoryEditor::open(&inventory_path(settings))?;
+    let inventory = editor.inventory()?;
+    let previous_issues: Vec<String> = validate::validate(&inventory).into_iter()
+        .map(|i| i.message)
+        .collect();
+
+    edit(&mut editor, &inventory)?;
+
+    let new_issues: Vec<validate::ValidationIssue> = validate::validate(&editor.inventory()?).into_iter()
+        .filter(|i| !previous_issues.contains(&i.message))
+        .collect();
+    if !new_issues.is_empty() {
+        for issue in &new_issues {
+            println!("{}", issue);
+        }
+
+        let msg = "The change would break the inventory layout, not saving it";
         error!("{}", msg);
-        return Err(Error::new(ErrorKind::Other, msg));
+        return Err(Error::other(msg));
     }
 
-    playbook.run(settings)
+    editor.save()?;
+    Ok(exit_status(true))
+}
+
+fn run_list_available_services(settings: &ClusterSettings) -> Result<ExitStatus, Error> {
+    let registry = ServiceRegistry::from_settings(settings)?;
+    let mut services: Vec<AvailableService> = registry.services().iter()
+        .map(|s| AvailableService {
+            name: s.name().to_string(),
+            description: s.description().to_string(),
+            dependencies: s.dependencies()
+        })
+        .collect();
+    services.sort_by(|a, b| a.name.cmp(&b.name));
+
+    output::print_records(settings.output.unwrap_or(OutputFormat::Table), &services)?;
+    Ok(exit_status(true))
+}
+
+/// Deploys services, after the services they depend on. Services already
+/// installed on all the machines are skipped, unless asked to redeploy them.
+fn run_deploy_service(settings: &ClusterSettings, options: &ServiceDeployOptions) -> Result<ExitStatus, Error> {
+    let registry = ServiceRegistry::from_settings(settings)?;
+    let names: Vec<&str> = options.services.iter().map(|s| s.as_str()).collect();
+    let services = registry.deploy_order(&names)?;
+    let values = ServiceValues::new(&services, &options.values, &options.values_files)?;
+    let executor = executor::executor(settings)?;
+    let installed = InstalledServices::load(settings, executor.as_ref(), CLUSTER_GROUP)?;
+
+    for service in services {
+        let redeploy = options.redeploy && names.contains(&service.name());
+        if installed.is_installed_everywhere(service.name()) && !redeploy {
+            info!("Service '{}' is already installed, skipping it", service.name());
+            continue;
+        }
+
+        info!("Deploying service '{}' to cluster", service.name());
+        let status = service.deploy(settings, executor.as_ref(), &values)?;
+        if !status.success() {
+            return Ok(status);
+        }
+    }
+
+    Ok(exit_status(true))
+}
+
+/// Deletes a service, and the services it depends on if asked to, refusing
+/// to delete the ones other installed services still need.
+fn run_delete_service(settings: &ClusterSettings, options: &ServiceDeleteOptions) -> Result<ExitStatus, Error> {
+    let registry = ServiceRegistry::from_settings(settings)?;
+    let executor = executor::executor(settings)?;
+    let installed = InstalledServices::load(settings, executor.as_ref(), CLUSTER_GROUP)?;
+    let services = registry.delete_order(&[options.service_options.service.as_str()], options.with_dependencies, &installed.names())?;
+    let values = ServiceValues::new(&services, &options.service_options.values, &options.service_options.values_files)?;
+
+    for service in services {
+        info!("Deleting service '{}' from cluster", service.name());
+        let status = service.delete(settings, executor.as_ref(), &values)?;
+        if !status.success() {
+            return Ok(status);
+        }
+    }
+
+    Ok(exit_status(true))
+}
+
+/// Runs an operation on a service from the registry.
+fn run_service_operation<F>(settings: &ClusterSettings, options: &ServiceCommandOptions, operation: F) -> Result<ExitStatus, Error>
+    where F: FnOnce(&dyn Service, &dyn Executor, &ServiceValues) -> Result<ExitStatus, Error> {
+    let registry = ServiceRegistry::from_settings(settings)?;
+    let service = registry.get(&options.service)?;
+    let values = ServiceValues::new(&[service], &options.values, &options.values_files)?;
+    let executor = executor::executor(settings)?;
+
+    operation(service, executor.as_ref(), &values)
+}
+
+/// Describes the variables of the available services, for the command line
+/// help. User-defined services that can't be loaded are left out.
+pub fn service_variables_help(services_dir: Option<&str>) -> String {
+    let registry = ServiceRegistry::with_services_dir(services_dir)
+        .unwrap_or_else(|_| ServiceRegistry::with_builtin_services());
+    services::variables_help(&registry)
+}
+
+/// Builds the exit status for commands that don't spawn an external process.
+fn exit_status(success: bool) -> ExitStatus {
+    ExitStatus::from_raw(if success { 0 } else { 1 << 8 })
 }
\ No newline at end of file
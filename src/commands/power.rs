@@ -0,0 +1,267 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::io::{self, Error, ErrorKind, Write};
+use std::net::SocketAddr;
+use std::process::{Command, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+
+use crate::commands::ansible::{AnsibleAggregatePlaybook, AnsibleCommand, AnsiblePlaybook};
+use crate::commands::executor::{self, Executor};
+use crate::commands::process;
+use crate::commands::results::{PowerOnResult, PowerOnState};
+use crate::commands::services::state::InstalledServices;
+use crate::commands::{exit_status, update, REBOOT_HOSTS_COMMAND_PLAYBOOK, SERVICE_NAME_K3S, SERVICE_NAME_KUBERNETES};
+use crate::utils::inventory::{Inventory, ALL_GROUP};
+use crate::utils::inventory::validate::{CLUSTER_GROUP, CLUSTER_MANAGERS_GROUP};
+use crate::utils::output;
+use crate::utils::settings::{ClusterSettings, OutputFormat, PoweronCommand, RebootCommand, ShutdownCommand};
+use crate::utils::wol;
+
+/// Order in which the machines are shut down: workers first, then managers,
+/// and the last manager of the cluster only after confirmation.
+#[derive(Debug, Default, PartialEq)]
+struct ShutdownPlan {
+    workers: Vec<String>,
+    managers: Vec<String>,
+    last_manager: Option<String>
+}
+
+/// Reboots the machines. When Kubernetes is deployed on the cluster, the
+/// nodes are rebooted one at a time, workers first, draining them before and
+/// waiting for them to be Ready again after, unless `--force` is given.
+pub fn run_reboot(settings: &ClusterSettings, options: &RebootCommand) -> Result<ExitStatus, Error> {
+    let executor = executor::executor(settings)?;
+    if options.force || !kubernetes_is_deployed(settings, executor.as_ref())? {
+        return executor.run_module(settings, &AnsibleCommand::new("reboot", true, settings.host_pattern.clone()));
+    }
+
+    let inventory = Inventory::from_settings(settings)?;
+    let hosts = update::rolling_order(&inventory, settings.host_pattern.as_deref().unwrap_or(ALL_GROUP))?;
+    let status = reboot_hosts(settings, executor.as_ref(), &hosts.join(","))?;
+    if !status.success() {
+        error!("Could not reboot all the machines, the ones after the failure were left untouched");
+    }
+
+    Ok(status)
+}
+
+/// Shuts down the machines, workers before managers. Taking down the last
+/// manager needs to be confirmed, unless `--force` is given.
+pub fn run_shutdown(settings: &ClusterSettings, options: &ShutdownCommand) -> Result<ExitStatus, Error> {
+    let executor = executor::executor(settings)?;
+    let shutdown = |host_pattern: Option<String>| {
+        executor.run_module(settings, &AnsibleCommand::new("community.general.shutdown", true, host_pattern))
+    };
+    if options.force {
+        return shutdown(settings.host_pattern.clone());
+    }
+
+    let inventory = Inventory::from_settings(settings)?;
+    let plan = shutdown_plan(&inventory, settings.host_pattern.as_deref().unwrap_or(ALL_GROUP))?;
+
+    let mut status = exit_status(true);
+    for hosts in [&plan.workers, &plan.managers].iter().filter(|h| !h.is_empty()) {
+        status = shutdown(Some(hosts.join(",")))?;
+        if !status.success() {
+            error!("Could not shut down {}, stopping here", hosts.join(", "));
+            return Ok(status);
+        }
+    }
+
+    if let Some(host) = plan.last_manager {
+        let question = format!("{} is the last manager of the cluster, shut it down too?", host);
+        if !settings.dry_run && !confirm(&question)? {
+            let msg = format!("Not shutting down {}, the last manager of the cluster", host);
+            error!("{}", msg);
+            return Err(Error::new(ErrorKind::Interrupted, msg));
+        }
+        status = shutdown(Some(host))?;
+    }
+
+    Ok(status)
+}
+
+/// Powers on the machines by sending Wake-on-LAN packets to the network cards
+/// in their `mac_address` variable, optionally waiting for them to answer
+/// ping.
+pub fn run_poweron(settings: &ClusterSettings, options: &PoweronCommand) -> Result<ExitStatus, Error> {
+    let inventory = Inventory::from_settings(settings)?;
+    let address = SocketAddr::new(options.broadcast, options.port);
+
+    let mut results = Vec::new();
+    for host in inventory.resolve_pattern(settings.host_pattern.as_deref().unwrap_or(ALL_GROUP))? {
+        let mac_address = inventory.host_var(&host.name, "mac_address").unwrap_or_default();
+        let state = if mac_address.is_empty() {
+            warn!("{} has no mac_address variable, so it can't be powered on", host.name);
+            PowerOnState::NoMacAddress
+        } else if let Ok(mac) = wol::parse_mac_address(&mac_address) {
+            if settings.dry_run {
+                writeln!(io::stdout(), "Wake-on-LAN {} ({}) through {}", host.name, mac_address, address)?;
+                PowerOnState::Sent
+            } else {
+                info!("Sending Wake-on-LAN packet to {} ({}) through {}", host.name, mac_address, address);
+                match wol::send(&mac, address) {
+                    Ok(()) => PowerOnState::Sent,
+                    Err(e) => {
+                        error!("Could not send the Wake-on-LAN packet to {}: {}", host.name, e);
+                        PowerOnState::SendFailed
+                    }
+                }
+            }
+        } else {
+            warn!("{} has an invalid mac_address variable, so it can't be powered on", host.name);
+            PowerOnState::InvalidMacAddress
+        };
+
+        results.push(PowerOnResult { host: host.name.clone(), mac_address, state });
+    }
+
+    if settings.dry_run {
+        return Ok(exit_status(true));
+    }
+    if options.wait {
+        wait_for_hosts(settings, &inventory, &mut results, Duration::from_secs(options.timeout))?;
+    }
+
+    output::print_records(settings.output.unwrap_or(OutputFormat::Table), &results)?;
+    Ok(exit_status(results.iter().all(|r| matches!(r.state, PowerOnState::Sent | PowerOnState::Up))))
+}
+
+/// Pings the machines the packets were sent to until all of them answer, or
+/// `timeout` passes, and marks them as up or down.
+fn wait_for_hosts(settings: &ClusterSettings, inventory: &Inventory, results: &mut [PowerOnResult], timeout: Duration) -> Result<(), Error> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        for result in results.iter_mut().filter(|r| r.state == PowerOnState::Sent) {
+            let address = inventory.host_var(&result.host, "ansible_host").unwrap_or_else(|| result.host.clone());
+            let ping = process::output(settings, Command::new("ping").args(["-c", "1", "-W", "1", &address]))?;
+            if ping.status.success() {
+                info!("{} is up", result.host);
+                result.state = PowerOnState::Up;
+            }
+        }
+
+        if results.iter().all(|r| r.state != PowerOnState::Sent) || Instant::now() >= deadline {
+            break;
+        }
+        thread::sleep(Duration::from_secs(2));
+    }
+
+    for result in results.iter_mut().filter(|r| r.state == PowerOnState::Sent) {
+        error!("{} didn't come up in {} seconds", result.host, timeout.as_secs());
+        result.state = PowerOnState::Down;
+    }
+
+    Ok(())
+}
+
+/// Reboots the machines in `host_pattern` one at a time, in the given order,
+/// waiting for each of them to be back before the next one, and stopping at
+/// the first one that fails. Kubernetes nodes are drained before, and
+/// uncordoned once they are Ready again.
+fn reboot_hosts(settings: &ClusterSettings, executor: &dyn Executor, host_pattern: &str) -> Result<ExitStatus, Error> {
+    let mut playbook = AnsibleAggregatePlaybook::new();
+    playbook.add_playbook(AnsiblePlaybook::load(REBOOT_HOSTS_COMMAND_PLAYBOOK));
+    let mut vars = serde_json::Map::new();
+    vars.insert("target_hosts".to_string(), serde_json::Value::from(host_pattern));
+    playbook.add_extra_vars(&vars);
+
+    let (status, results) = executor.run_playbook_with_results(settings, &playbook)?;
+    for result in results.iter().filter(|r| !r.is_success()) {
+        error!("Could not reboot {}: {}", result.host, result.output());
+    }
+
+    Ok(status)
+}
+
+fn kubernetes_is_deployed(settings: &ClusterSettings, executor: &dyn Executor) -> Result<bool, Error> {
+    let installed = InstalledServices::load(settings, executor, CLUSTER_GROUP)?.names();
+    Ok(installed.iter().any(|s| s == SERVICE_NAME_KUBERNETES || s == SERVICE_NAME_K3S))
+}
+
+/// Splits the machines matched by `pattern` in the order they're shut down.
+/// The main master is the last manager, and it only needs confirmation when
+/// all the managers are going down.
+fn shutdown_plan(inventory: &Inventory, pattern: &str) -> Result<ShutdownPlan, Error> {
+    let all_managers: Vec<&str> = inventory.group_hosts(CLUSTER_MANAGERS_GROUP).iter().map(|h| h.name.as_str()).collect();
+    let mut plan = ShutdownPlan::default();
+    for host in inventory.resolve_pattern(pattern)? {
+        if all_managers.contains(&host.name.as_str()) {
+            plan.managers.push(host.name.clone());
+        } else {
+            plan.workers.push(host.name.clone());
+        }
+    }
+
+    if !plan.managers.is_empty() && plan.managers.len() == all_managers.len() {
+        plan.managers.retain(|h| h != all_managers[0]);
+        plan.last_manager = Some(all_managers[0].to_string());
+    }
+
+    Ok(plan)
+}
+
+/// Asks the user a yes/no question, which is answered with a no when there's
+/// nobody to answer it.
+fn confirm(question: &str) -> Result<bool, Error> {
+    let mut stderr = io::stderr();
+    write!(stderr, "{} [y/N]: ", question)?;
+    stderr.flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use rstest::rstest;
+    use crate::utils::inventory::Inventory;
+    use super::{shutdown_plan, ShutdownPlan};
+
+    const INVENTORY: &str = "\
+[cluster_managers]
+master1
+master2
+
+[cluster_workers]
+worker1
+worker2
+
+[cluster:children]
+cluster_managers
+cluster_workers
+";
+
+    fn names(hosts: &[&str]) -> Vec<String> {
+        hosts.iter().map(|h| h.to_string()).collect()
+    }
+
+    #[rstest]
+    #[case("all", &["worker1", "worker2"], &["master2"], Some("master1"))]
+    #[case("cluster_workers", &["worker1", "worker2"], &[], None)]
+    #[case("master2:worker1", &["worker1"], &["master2"], None)]
+    #[case("master*", &[], &["master2"], Some("master1"))]
+    fn workers_are_shut_down_before_managers(
+        #[case] pattern: &str,
+        #[case] workers: &[&str],
+        #[case] managers: &[&str],
+        #[case] last_manager: Option<&str>) {
+        let inventory = Inventory::parse_ini(INVENTORY, Path::new("inventory")).unwrap();
+        let expected = ShutdownPlan {
+            workers: names(workers),
+            managers: names(managers),
+            last_manager: last_manager.map(|h| h.to_string())
+        };
+
+        assert_eq!(shutdown_plan(&inventory, pattern).unwrap(), expected);
+    }
+}
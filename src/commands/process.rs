@@ -0,0 +1,112 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::io::{self, Error, Write};
+use std::process::{Command, ExitStatus, Output, Stdio};
+
+use log::info;
+
+use crate::commands::exit_status;
+use crate::utils::settings::ClusterSettings;
+
+/// Runs a command attached to the terminal, or just prints it when running
+/// with `--dry-run`.
+pub fn run(settings: &ClusterSettings, command: &mut Command) -> Result<ExitStatus, Error> {
+    if settings.dry_run {
+        print_command(command)?;
+        return Ok(exit_status(true));
+    }
+
+    info!("Executing {}", command_line(command));
+    command
+        .stdin(Stdio::piped())
+        .status()
+}
+
+/// Runs a command letting it interact with the terminal, but capturing its
+/// standard output.
+pub fn run_captured(command: &mut Command) -> Result<(ExitStatus, String), Error> {
+    info!("Executing {}", command_line(command));
+    let output = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    Ok((output.status, String::from_utf8_lossy(&output.stdout).to_string()))
+}
+
+/// Runs a command without any interaction, capturing all its output. When
+/// running with `--dry-run`, the command is printed and an empty successful
+/// output is returned.
+pub fn output(settings: &ClusterSettings, command: &mut Command) -> Result<Output, Error> {
+    if settings.dry_run {
+        print_command(command)?;
+        return Ok(Output { status: exit_status(true), stdout: Vec::new(), stderr: Vec::new() });
+    }
+
+    info!("Executing {}", command_line(command));
+    command
+        .stdin(Stdio::null())
+        .output()
+}
+
+pub fn print_command(command: &Command) -> Result<(), Error> {
+    writeln!(io::stdout(), "{}", command_line(command))
+}
+
+/// Formats a command as it would be typed in a shell, including the
+/// environment variables it sets.
+pub fn command_line(command: &Command) -> String {
+    let mut words: Vec<String> = command.get_envs()
+        .filter_map(|(key, value)| value.map(|v| format!("{}={}", key.to_string_lossy(), quote_if_needed(&v.to_string_lossy()))))
+        .collect();
+
+    words.push(quote_if_needed(&command.get_program().to_string_lossy()));
+    words.extend(command.get_args().map(|a| quote_if_needed(&a.to_string_lossy())));
+
+    words.join(" ")
+}
+
+/// Quotes text so that a POSIX shell reads it as a single word.
+pub fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+fn quote_if_needed(text: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:@%+,".contains(c);
+    if !text.is_empty() && text.chars().all(is_safe) {
+        text.to_string()
+    } else {
+        shell_quote(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+    use rstest::rstest;
+    use super::{command_line, shell_quote};
+
+    #[rstest]
+    fn command_lines_are_shell_quoted() {
+        let mut command = Command::new("ansible");
+        command
+            .env("ANSIBLE_STDOUT_CALLBACK", "json")
+            .args(["--inventory", "/tmp/hosts", "-a", "echo \"it's\"", "all"]);
+
+        assert_eq!(command_line(&command),
+                   "ANSIBLE_STDOUT_CALLBACK=json ansible --inventory /tmp/hosts -a 'echo \"it'\\''s\"' all");
+    }
+
+    #[rstest]
+    #[case("ls", "'ls'")]
+    #[case("/my dir", "'/my dir'")]
+    #[case("it's", "'it'\\''s'")]
+    fn text_is_correctly_quoted(#[case] text: &str, #[case] expected: &str) {
+        assert_eq!(shell_quote(text), expected);
+    }
+}
@@ -0,0 +1,427 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use serde::Serialize;
+use strum_macros::Display;
+
+use crate::utils::output::TableRow;
+
+/// Outcome of running a command on a host.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum HostStatus {
+    Ok,
+    Changed,
+    Failed,
+    Skipped,
+    Unreachable
+}
+
+/// Result of running a command on a single host.
+#[derive(Clone, Debug, Serialize)]
+pub struct HostResult {
+    pub host: String,
+    pub status: HostStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rc: Option<i64>,
+    pub stdout: String,
+    pub stderr: String,
+    pub msg: String,
+    #[serde(skip_serializing_if = "serde_json::Value::is_null")]
+    pub facts: serde_json::Value
+}
+
+impl HostResult {
+    pub fn is_success(&self) -> bool {
+        !matches!(self.status, HostStatus::Failed | HostStatus::Unreachable)
+    }
+
+    /// Returns the most relevant output of the command.
+    pub fn output(&self) -> String {
+        let mut output = if self.stdout.is_empty() { self.msg.clone() } else { self.stdout.clone() };
+        if !self.is_success() && !self.stderr.is_empty() {
+            if !output.is_empty() {
+                output.push('\n');
+            }
+            output.push_str(&self.stderr);
+        }
+
+        output
+    }
+}
+
+impl TableRow for HostResult {
+    fn headers() -> Vec<&'static str> {
+        vec!["host", "status", "rc", "output"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.host.clone(),
+            self.status.to_string(),
+            self.rc.map(|rc| rc.to_string()).unwrap_or_default(),
+            self.output()
+        ]
+    }
+}
+
+/// Summary of a playbook run on a single host.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PlaybookHostStats {
+    pub host: String,
+    pub ok: u64,
+    pub changed: u64,
+    pub failed: u64,
+    pub unreachable: u64,
+    pub skipped: u64
+}
+
+impl TableRow for PlaybookHostStats {
+    fn headers() -> Vec<&'static str> {
+        vec!["host", "ok", "changed", "failed", "unreachable", "skipped"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.host.clone(),
+            self.ok.to_string(),
+            self.changed.to_string(),
+            self.failed.to_string(),
+            self.unreachable.to_string(),
+            self.skipped.to_string()
+        ]
+    }
+}
+
+/// State of a system service on a host.
+#[derive(Clone, Debug, Serialize)]
+pub struct ServiceState {
+    pub host: String,
+    pub service: String,
+    pub state: String,
+    pub status: String
+}
+
+impl ServiceState {
+    /// Extracts the services reported by Ansible's `service_facts` module.
+    pub fn from_results(results: &[HostResult]) -> Vec<ServiceState> {
+        let mut states = Vec::new();
+
+        for result in results {
+            if let Some(services) = result.facts.get("services").and_then(|s| s.as_object()) {
+                for (name, service) in services {
+                    let field = |key: &str| service.get(key).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+                    states.push(ServiceState {
+                        host: result.host.clone(),
+                        service: name.clone(),
+                        state: field("state"),
+                        status: field("status")
+                    });
+                }
+            }
+        }
+
+        states
+    }
+}
+
+impl TableRow for ServiceState {
+    fn headers() -> Vec<&'static str> {
+        vec!["host", "service", "state", "status"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![self.host.clone(), self.service.clone(), self.state.clone(), self.status.clone()]
+    }
+}
+
+/// Health of a component of a service on a host.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ServiceHealth {
+    pub host: String,
+    pub service: String,
+    pub component: String,
+    pub state: String,
+    pub healthy: bool
+}
+
+impl ServiceHealth {
+    /// Extracts the `service_status` fact set by the status playbooks of a
+    /// service, which lists the `component`, `state` and `healthy` flag of
+    /// every part of the service. Hosts not reporting it get a single entry
+    /// telling whether the playbooks succeeded on them.
+    pub fn from_results(service: &str, results: &[HostResult]) -> Vec<ServiceHealth> {
+        let mut hosts: Vec<&str> = Vec::new();
+        for result in results {
+            if !hosts.contains(&result.host.as_str()) {
+                hosts.push(&result.host);
+            }
+        }
+
+        let mut health = Vec::new();
+        for host in hosts {
+            let host_results: Vec<&HostResult> = results.iter().filter(|r| r.host == host).collect();
+            let reported = host_results.iter().rev()
+                .find_map(|r| r.facts.get("service_status").and_then(|s| s.as_array()));
+
+            match reported {
+                Some(components) => {
+                    for component in components {
+                        let field = |key: &str| fact_string(&component[key]);
+                        health.push(ServiceHealth {
+                            host: host.to_string(),
+                            service: service.to_string(),
+                            component: field("component"),
+                            state: field("state"),
+                            healthy: fact_bool(&component["healthy"])
+                        });
+                    }
+                },
+                None => {
+                    let failure = host_results.iter()
+                        .map(|r| r.status)
+                        .find(|s| *s == HostStatus::Unreachable)
+                        .or_else(|| host_results.iter().map(|r| r.status).find(|s| *s == HostStatus::Failed));
+                    health.push(ServiceHealth {
+                        host: host.to_string(),
+                        service: service.to_string(),
+                        component: "playbook".to_string(),
+                        state: failure.unwrap_or(HostStatus::Ok).to_string(),
+                        healthy: failure.is_none()
+                    });
+                }
+            }
+        }
+
+        health
+    }
+}
+
+impl TableRow for ServiceHealth {
+    fn headers() -> Vec<&'static str> {
+        vec!["host", "component", "state", "healthy"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.host.clone(),
+            self.component.clone(),
+            self.state.clone(),
+            if self.healthy { "yes" } else { "no" }.to_string()
+        ]
+    }
+}
+
+/// A package that can be upgraded on a host.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PendingUpdate {
+    pub host: String,
+    pub package: String,
+    pub current: String,
+    pub candidate: String,
+    pub security: bool
+}
+
+impl PendingUpdate {
+    /// Extracts the `pending_updates` fact set by the check playbook, which
+    /// lists the `package`, `current` and `candidate` versions and the
+    /// `security` flag of every upgradable package.
+    pub fn from_results(results: &[HostResult]) -> Vec<PendingUpdate> {
+        let mut updates = Vec::new();
+
+        for result in results {
+            if let Some(packages) = result.facts.get("pending_updates").and_then(|p| p.as_array()) {
+                for package in packages {
+                    let field = |key: &str| fact_string(&package[key]);
+                    updates.push(PendingUpdate {
+                        host: result.host.clone(),
+                        package: field("package"),
+                        current: field("current"),
+                        candidate: field("candidate"),
+                        security: fact_bool(&package["security"])
+                    });
+                }
+            }
+        }
+
+        updates
+    }
+}
+
+impl TableRow for PendingUpdate {
+    fn headers() -> Vec<&'static str> {
+        vec!["host", "package", "current", "candidate", "security"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.host.clone(),
+            self.package.clone(),
+            self.current.clone(),
+            self.candidate.clone(),
+            if self.security { "yes" } else { "no" }.to_string()
+        ]
+    }
+}
+
+/// Outcome of updating the packages on a host.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct UpdateResult {
+    pub host: String,
+    pub status: HostStatus,
+    pub reboot_required: bool,
+    pub msg: String
+}
+
+impl UpdateResult {
+    /// Summarizes the tasks run on each host by the update playbook, with
+    /// the `reboot_required` fact it sets at the end.
+    pub fn from_results(results: &[HostResult]) -> Vec<UpdateResult> {
+        let mut updates: Vec<UpdateResult> = Vec::new();
+
+        for result in results {
+            let index = match updates.iter().position(|u| u.host == result.host) {
+                Some(i) => i,
+                None => {
+                    updates.push(UpdateResult {
+                        host: result.host.clone(),
+                        status: HostStatus::Ok,
+                        reboot_required: false,
+                        msg: String::new()
+                    });
+                    updates.len() - 1
+                }
+            };
+            let update = &mut updates[index];
+
+            if status_severity(result.status) > status_severity(update.status) {
+                update.status = result.status;
+                if !result.is_success() {
+                    update.msg = result.output();
+                }
+            }
+            if let Some(reboot) = result.facts.get("reboot_required") {
+                update.reboot_required = fact_bool(reboot);
+            }
+        }
+
+        updates
+    }
+}
+
+impl TableRow for UpdateResult {
+    fn headers() -> Vec<&'static str> {
+        vec!["host", "status", "reboot required", "output"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.host.clone(),
+            self.status.to_string(),
+            if self.reboot_required { "yes" } else { "no" }.to_string(),
+            self.msg.clone()
+        ]
+    }
+}
+
+/// Whether a machine woken up with Wake-on-LAN came up.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum PowerOnState {
+    /// The magic packet was sent, without waiting for the machine.
+    Sent,
+    Up,
+    Down,
+    /// The machine has no `mac_address` variable in the inventory.
+    NoMacAddress,
+    /// The `mac_address` variable of the machine can't be parsed.
+    InvalidMacAddress,
+    /// The magic packet couldn't be sent, like when there's no route to the
+    /// broadcast address.
+    SendFailed
+}
+
+/// Outcome of powering on a machine with Wake-on-LAN.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PowerOnResult {
+    pub host: String,
+    pub mac_address: String,
+    pub state: PowerOnState
+}
+
+impl TableRow for PowerOnResult {
+    fn headers() -> Vec<&'static str> {
+        vec!["host", "mac address", "state"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![self.host.clone(), self.mac_address.clone(), self.state.to_string()]
+    }
+}
+
+/// Orders the status of the tasks on a host, so that the summary of the host
+/// shows the most relevant one.
+fn status_severity(status: HostStatus) -> u8 {
+    match status {
+        HostStatus::Skipped => 0,
+        HostStatus::Ok => 1,
+        HostStatus::Changed => 2,
+        HostStatus::Failed => 3,
+        HostStatus::Unreachable => 4
+    }
+}
+
+/// Playbooks can set facts to strings even when they hold other types, so
+/// they are turned into strings before being used.
+fn fact_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => String::new(),
+        other => other.to_string()
+    }
+}
+
+fn fact_bool(value: &serde_json::Value) -> bool {
+    matches!(fact_string(value).to_lowercase().as_str(), "true" | "yes")
+}
+
+/// A host in the inventory, with its address and groups.
+#[derive(Clone, Debug, Serialize)]
+pub struct InventoryHost {
+    pub host: String,
+    pub address: String,
+    pub groups: Vec<String>
+}
+
+impl TableRow for InventoryHost {
+    fn headers() -> Vec<&'static str> {
+        vec!["host", "address", "groups"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![self.host.clone(), self.address.clone(), self.groups.join(",")]
+    }
+}
+
+/// A service that can be deployed on the cluster.
+#[derive(Clone, Debug, Serialize)]
+pub struct AvailableService {
+    pub name: String,
+    pub description: String,
+    pub dependencies: Vec<String>
+}
+
+impl TableRow for AvailableService {
+    fn headers() -> Vec<&'static str> {
+        vec!["name", "description", "dependencies"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![self.name.clone(), self.description.clone(), self.dependencies.join(",")]
+    }
+}
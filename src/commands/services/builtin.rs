@@ -0,0 +1,70 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use crate::commands::ansible::AnsiblePlaybook;
+use crate::commands::services::{PlaybookService, Service};
+use crate::commands::{
+    INSTALL_DOCKER_COMMAND_PLAYBOOK, INSTALL_KUBERNETES_COMMAND_PLAYBOOK, SETUP_KUBERNETES_CLUSTER_COMMAND_PLAYBOOK,
+    STATUS_DOCKER_COMMAND_PLAYBOOK, STATUS_KUBERNETES_COMMAND_PLAYBOOK, UNINSTALL_DOCKER_COMMAND_PLAYBOOK,
+    UNINSTALL_KUBERNETES_COMMAND_PLAYBOOK, UPGRADE_KUBERNETES_COMMAND_PLAYBOOK, JOIN_KUBERNETES_COMMAND_PLAYBOOK,
+    LEAVE_KUBERNETES_COMMAND_PLAYBOOK, INSTALL_K3S_COMMAND_PLAYBOOK, UNINSTALL_K3S_COMMAND_PLAYBOOK, STATUS_K3S_COMMAND_PLAYBOOK,
+    INSTALL_CONTAINERD_COMMAND_PLAYBOOK, UNINSTALL_CONTAINERD_COMMAND_PLAYBOOK, INSTALL_PODMAN_COMMAND_PLAYBOOK,
+    UNINSTALL_PODMAN_COMMAND_PLAYBOOK, INSTALL_DOCKER_SWARM_COMMAND_PLAYBOOK, UNINSTALL_DOCKER_SWARM_COMMAND_PLAYBOOK,
+    STATUS_DOCKER_SWARM_COMMAND_PLAYBOOK, SERVICE_NAME_CONTAINERD, SERVICE_NAME_DOCKER, SERVICE_NAME_DOCKER_SWARM,
+    SERVICE_NAME_KUBERNETES, SERVICE_NAME_K3S, SERVICE_NAME_PODMAN
+};
+
+/// Services shipped with clusterctl.
+pub fn services() -> Vec<Box<dyn Service>> {
+    vec![
+        Box::new(PlaybookService::new(SERVICE_NAME_DOCKER, "Docker container engine")
+            .with_deploy_playbook(AnsiblePlaybook::load(INSTALL_DOCKER_COMMAND_PLAYBOOK))
+            .with_delete_playbook(AnsiblePlaybook::load(UNINSTALL_DOCKER_COMMAND_PLAYBOOK))
+            .with_status_playbook(AnsiblePlaybook::load(STATUS_DOCKER_COMMAND_PLAYBOOK))),
+
+        Box::new(PlaybookService::new(SERVICE_NAME_DOCKER_SWARM, "Docker Swarm cluster, with the cluster managers as swarm managers")
+            .with_dependency(SERVICE_NAME_DOCKER)
+            .with_deploy_playbook(AnsiblePlaybook::load(INSTALL_DOCKER_SWARM_COMMAND_PLAYBOOK))
+            .with_delete_playbook(AnsiblePlaybook::load(UNINSTALL_DOCKER_SWARM_COMMAND_PLAYBOOK))
+            .with_status_playbook(AnsiblePlaybook::load(STATUS_DOCKER_SWARM_COMMAND_PLAYBOOK))),
+
+        Box::new(PlaybookService::new(SERVICE_NAME_CONTAINERD, "containerd container runtime")
+            .with_deploy_playbook(AnsiblePlaybook::load(INSTALL_CONTAINERD_COMMAND_PLAYBOOK))
+            .with_delete_playbook(AnsiblePlaybook::load(UNINSTALL_CONTAINERD_COMMAND_PLAYBOOK))),
+
+        Box::new(PlaybookService::new(SERVICE_NAME_PODMAN, "Podman daemonless container engine")
+            .with_deploy_playbook(AnsiblePlaybook::load(INSTALL_PODMAN_COMMAND_PLAYBOOK))
+            .with_delete_playbook(AnsiblePlaybook::load(UNINSTALL_PODMAN_COMMAND_PLAYBOOK))),
+
+        Box::new(PlaybookService::new(SERVICE_NAME_KUBERNETES, "Kubernetes cluster, set up with kubeadm")
+            .with_dependency(SERVICE_NAME_CONTAINERD)
+            .with_variable("cni_plugin", Some("flannel"), "Network add-on to install: flannel, calico, cilium or weave")
+            .with_variable("cni_version", Some("the tested version of the add-on"), "Version of the network add-on")
+            .with_variable("pod_network_cidr", Some("the add-on's default range"), "Network range for the pods")
+            .with_variable("cni_manifest_url", Some("the add-on's manifest for its version"), "Manifest of the network add-on to install")
+            .with_file_variable("cni_manifest_file", "Local manifest of the network add-on, for clusters without Internet access")
+            .with_variable("control_plane_vip", None, "Virtual IP for the API servers, held by keepalived and balanced by haproxy on the masters")
+            .with_variable("control_plane_vip_interface", Some("the masters' default interface"), "Network interface for the virtual IP")
+            .with_variable("control_plane_endpoint", Some("the virtual IP or the main master"), "Address and port used to reach the API servers")
+            .with_variable("kubeconfig_user", Some("the Ansible user"), "User on the main master to set up kubectl for")
+            .with_deploy_playbook(AnsiblePlaybook::load(INSTALL_KUBERNETES_COMMAND_PLAYBOOK))
+            .with_deploy_playbook(AnsiblePlaybook::load(SETUP_KUBERNETES_CLUSTER_COMMAND_PLAYBOOK))
+            .with_delete_playbook(AnsiblePlaybook::load(UNINSTALL_KUBERNETES_COMMAND_PLAYBOOK))
+            .with_status_playbook(AnsiblePlaybook::load(STATUS_KUBERNETES_COMMAND_PLAYBOOK))
+            .with_upgrade_playbook(AnsiblePlaybook::load(UPGRADE_KUBERNETES_COMMAND_PLAYBOOK))
+            .with_join_playbook(AnsiblePlaybook::load(INSTALL_CONTAINERD_COMMAND_PLAYBOOK))
+            .with_join_playbook(AnsiblePlaybook::load(INSTALL_KUBERNETES_COMMAND_PLAYBOOK))
+            .with_join_playbook(AnsiblePlaybook::load(JOIN_KUBERNETES_COMMAND_PLAYBOOK))
+            .with_leave_playbook(AnsiblePlaybook::load(LEAVE_KUBERNETES_COMMAND_PLAYBOOK))),
+
+        Box::new(PlaybookService::new(SERVICE_NAME_K3S, "Lightweight Kubernetes cluster, set up with k3s")
+            .with_variable("k3s_version", Some("the latest stable release"), "Version of k3s to install, like v1.22.2+k3s1")
+            .with_variable("k3s_server_args", None, "Extra arguments for the k3s servers")
+            .with_deploy_playbook(AnsiblePlaybook::load(INSTALL_K3S_COMMAND_PLAYBOOK))
+            .with_delete_playbook(AnsiblePlaybook::load(UNINSTALL_K3S_COMMAND_PLAYBOOK))
+            .with_status_playbook(AnsiblePlaybook::load(STATUS_K3S_COMMAND_PLAYBOOK)))
+    ]
+}
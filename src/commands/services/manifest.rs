@@ -0,0 +1,204 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use log::{error, info};
+use serde::Deserialize;
+
+use crate::commands::ansible::AnsiblePlaybook;
+use crate::commands::services::PlaybookService;
+use crate::utils::inventory::value_to_string;
+
+/// Name of the manifest file describing a user-defined service.
+const MANIFEST_FILE: &str = "service.yaml";
+
+/// Manifest of a user-defined service, with playbook paths relative to it.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServiceManifest {
+    name: Option<String>,
+    description: String,
+    dependencies: Vec<String>,
+    variables: BTreeMap<String, ManifestVariable>,
+    playbooks: ManifestPlaybooks
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ManifestVariable {
+    description: String,
+    default: Option<serde_yaml::Value>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ManifestPlaybooks {
+    deploy: PlaybookList,
+    delete: PlaybookList,
+    status: PlaybookList,
+    upgrade: PlaybookList,
+    join: PlaybookList,
+    leave: PlaybookList
+}
+
+/// One or several playbook files.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PlaybookList {
+    One(String),
+    Many(Vec<String>)
+}
+
+impl Default for PlaybookList {
+    fn default() -> Self {
+        PlaybookList::Many(Vec::new())
+    }
+}
+
+impl PlaybookList {
+    fn load(&self, dir: &Path) -> Result<Vec<AnsiblePlaybook>, Error> {
+        let files = match self {
+            PlaybookList::One(f) => vec![f.clone()],
+            PlaybookList::Many(f) => f.clone()
+        };
+
+        files.iter()
+            .map(|f| AnsiblePlaybook::load_from_file(&dir.join(f)))
+            .collect()
+    }
+}
+
+/// Loads the services defined in the subdirectories of `dir`, in name order.
+/// Subdirectories without a manifest are ignored.
+pub fn load_services(dir: &Path) -> Result<Vec<PlaybookService>, Error> {
+    let mut service_dirs: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| Error::new(e.kind(), format!("Could not read services directory {}: {}", dir.display(), e)))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.join(MANIFEST_FILE).is_file())
+        .collect();
+    service_dirs.sort();
+
+    service_dirs.iter()
+        .map(|d| load_service(d))
+        .collect()
+}
+
+/// Loads the service defined by the manifest in `dir`.
+fn load_service(dir: &Path) -> Result<PlaybookService, Error> {
+    let manifest_path = dir.join(MANIFEST_FILE);
+    info!("Loading service from {}", manifest_path.display());
+
+    let contents = fs::read_to_string(&manifest_path)?;
+    let manifest: ServiceManifest = serde_yaml::from_str(&contents)
+        .map_err(|e| {
+            let msg = format!("{}: {}", manifest_path.display(), e);
+            error!("{}", msg);
+            Error::new(ErrorKind::InvalidData, msg)
+        })?;
+
+    let name = match &manifest.name {
+        Some(n) => n.clone(),
+        None => dir.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+    };
+    let mut service = PlaybookService::new(&name, &manifest.description);
+    for dependency in &manifest.dependencies {
+        service = service.with_dependency(dependency);
+    }
+    for (name, variable) in &manifest.variables {
+        let default = variable.default.as_ref().and_then(value_to_string);
+        service = service.with_variable(name, default.as_deref(), &variable.description);
+    }
+    for playbook in manifest.playbooks.deploy.load(dir)? {
+        service = service.with_deploy_playbook(playbook);
+    }
+    for playbook in manifest.playbooks.delete.load(dir)? {
+        service = service.with_delete_playbook(playbook);
+    }
+    for playbook in manifest.playbooks.status.load(dir)? {
+        service = service.with_status_playbook(playbook);
+    }
+    for playbook in manifest.playbooks.upgrade.load(dir)? {
+        service = service.with_upgrade_playbook(playbook);
+    }
+    for playbook in manifest.playbooks.join.load(dir)? {
+        service = service.with_join_playbook(playbook);
+    }
+    for playbook in manifest.playbooks.leave.load(dir)? {
+        service = service.with_leave_playbook(playbook);
+    }
+
+    Ok(service)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use rstest::rstest;
+    use tempfile::TempDir;
+    use crate::commands::services::Service;
+    use super::{load_services, MANIFEST_FILE};
+
+    const PLAYBOOK: &str = "- hosts: cluster\n  tasks: []\n";
+
+    fn write_service(dir: &Path, name: &str, manifest: &str, playbooks: &[&str]) {
+        let service_dir = dir.join(name);
+        fs::create_dir_all(&service_dir).unwrap();
+        fs::write(service_dir.join(MANIFEST_FILE), manifest).unwrap();
+        for playbook in playbooks {
+            fs::write(service_dir.join(playbook), PLAYBOOK).unwrap();
+        }
+    }
+
+    #[rstest]
+    fn services_are_loaded_from_manifests() {
+        let dir = TempDir::new().unwrap();
+        write_service(dir.path(), "nfs", "\
+name: nfs-client
+description: NFS client mounts
+dependencies: [docker]
+variables:
+  nfs_server:
+    description: NFS server address
+  mount_point:
+    description: Where to mount the share
+    default: /mnt/nfs
+playbooks:
+  deploy: [install.yaml, mount.yaml]
+  delete: uninstall.yaml
+", &["install.yaml", "mount.yaml", "uninstall.yaml"]);
+        write_service(dir.path(), "node-exporter", "\
+description: Prometheus node exporter
+playbooks:
+  deploy: deploy.yaml
+", &["deploy.yaml"]);
+        fs::create_dir(dir.path().join("not-a-service")).unwrap();
+
+        let services = load_services(dir.path()).unwrap();
+        let names: Vec<&str> = services.iter().map(|s| s.name()).collect();
+        assert_eq!(names, vec!["nfs-client", "node-exporter"]);
+        assert_eq!(services[0].description(), "NFS client mounts");
+        assert_eq!(services[0].dependencies(), vec!["docker"]);
+        let variables: Vec<(String, Option<String>)> = services[0].variables().into_iter().map(|v| (v.name, v.default)).collect();
+        assert_eq!(variables, vec![("mount_point".to_string(), Some("/mnt/nfs".to_string())), ("nfs_server".to_string(), None)]);
+        assert!(services[1].dependencies().is_empty());
+    }
+
+    #[rstest]
+    #[case("playbooks:\n  deploy: missing.yaml\n")]
+    #[case("playbooks:\n  install: deploy.yaml\n")]
+    #[case("dependencies: docker\n")]
+    fn invalid_manifests_are_rejected(#[case] manifest: &str) {
+        let dir = TempDir::new().unwrap();
+        write_service(dir.path(), "broken", manifest, &["deploy.yaml"]);
+
+        assert!(load_services(dir.path()).is_err());
+    }
+}
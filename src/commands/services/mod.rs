@@ -0,0 +1,707 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::process::ExitStatus;
+
+use log::{error, info, warn};
+
+use crate::commands::ansible::{AnsibleAggregatePlaybook, AnsiblePlaybook};
+use crate::commands::executor::Executor;
+use crate::commands::exit_status;
+use crate::commands::results::ServiceHealth;
+use crate::utils::output;
+use crate::utils::settings::{ClusterSettings, OutputFormat};
+
+mod builtin;
+mod manifest;
+pub mod state;
+
+/// A service that can be deployed on the cluster.
+pub trait Service {
+    fn name(&self) -> &str;
+
+    fn description(&self) -> &str;
+
+    /// Names of the services that need to be deployed before this one.
+    fn dependencies(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Variables that can be set when operating on the service.
+    fn variables(&self) -> Vec<ServiceVariable> {
+        Vec::new()
+    }
+
+    fn deploy(&self, settings: &ClusterSettings, executor: &dyn Executor, values: &ServiceValues) -> Result<ExitStatus, Error>;
+
+    fn delete(&self, settings: &ClusterSettings, executor: &dyn Executor, values: &ServiceValues) -> Result<ExitStatus, Error>;
+
+    fn status(&self, _settings: &ClusterSettings, _executor: &dyn Executor, _values: &ServiceValues) -> Result<ExitStatus, Error> {
+        Err(unsupported_operation(self.name(), "status"))
+    }
+
+    fn upgrade(&self, _settings: &ClusterSettings, _executor: &dyn Executor, _values: &ServiceValues) -> Result<ExitStatus, Error> {
+        Err(unsupported_operation(self.name(), "upgrade"))
+    }
+
+    /// Adds the hosts in `target_hosts` to a service already deployed on the cluster.
+    fn join(&self, _settings: &ClusterSettings, _executor: &dyn Executor, _values: &ServiceValues) -> Result<ExitStatus, Error> {
+        Err(unsupported_operation(self.name(), "join"))
+    }
+
+    /// Removes the hosts in `target_hosts` from the service, leaving the rest untouched.
+    fn leave(&self, _settings: &ClusterSettings, _executor: &dyn Executor, _values: &ServiceValues) -> Result<ExitStatus, Error> {
+        Err(unsupported_operation(self.name(), "leave"))
+    }
+}
+
+/// A variable a service can be configured with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServiceVariable {
+    pub name: String,
+    pub description: String,
+    /// Value used when the variable isn't set. This is only informative, the
+    /// actual default lives in the playbooks so that values from the
+    /// inventory are not overridden.
+    pub default: Option<String>,
+    /// Whether the value is the path of a local file, which is made absolute
+    /// as the playbooks don't run from the current directory.
+    pub file: bool
+}
+
+/// Values given for the variables of a service, which are passed to its
+/// playbooks as extra variables.
+#[derive(Clone, Debug, Default)]
+pub struct ServiceValues {
+    pub vars: serde_json::Map<String, serde_json::Value>,
+    pub files: Vec<String>
+}
+
+impl ServiceValues {
+    /// Checks `values` only set variables declared by the services, and that
+    /// the values files exist. The last service is the one the user asked for,
+    /// and the others the ones it depends on.
+    pub fn new(services: &[&dyn Service], values: &[(String, String)], files: &[String]) -> Result<ServiceValues, Error> {
+        let service = match services.last() {
+            Some(s) => s,
+            None => return Ok(ServiceValues::default())
+        };
+        let variables: Vec<ServiceVariable> = services.iter().flat_map(|s| s.variables()).collect();
+        let names: Vec<&str> = variables.iter().map(|v| v.name.as_str()).collect();
+
+        let mut vars = serde_json::Map::new();
+        for (key, value) in values {
+            if !names.contains(&key.as_str()) {
+                let msg = match similar_names(key, &names).first() {
+                    Some(similar) => format!("Service '{}' has no variable '{}'. Did you mean '{}'?", service.name(), key, similar),
+                    None => format!("Service '{}' has no variable '{}'", service.name(), key)
+                };
+                error!("{}", msg);
+                return Err(Error::new(ErrorKind::InvalidInput, msg));
+            }
+            let value = if variables.iter().any(|v| v.name == *key && v.file) {
+                absolute_path(key, value)?
+            } else {
+                value.clone()
+            };
+            vars.insert(key.clone(), serde_json::Value::String(value));
+        }
+
+        for file in files {
+            if !Path::new(file).is_file() {
+                let msg = format!("Values file {} doesn't exist", file);
+                error!("{}", msg);
+                return Err(Error::new(ErrorKind::NotFound, msg));
+            }
+        }
+
+        Ok(ServiceValues {
+            vars,
+            files: files.to_vec()
+        })
+    }
+
+    /// Adds the version to upgrade the service to, which is passed to the
+    /// playbooks as `service_version`.
+    pub fn with_version(&self, version: Option<&str>) -> ServiceValues {
+        match version {
+            Some(v) => self.with_var("service_version", v),
+            None => self.clone()
+        }
+    }
+
+    /// Adds the hosts to operate on, which is passed to the playbooks as
+    /// `target_hosts`, failing if no host pattern was given.
+    pub fn with_target_hosts(&self, host_pattern: Option<&str>, operation: &str) -> Result<ServiceValues, Error> {
+        match host_pattern {
+            Some(pattern) => Ok(self.with_var("target_hosts", pattern)),
+            None => {
+                let msg = format!("Specify the hosts to {} with --host-pattern", operation);
+                error!("{}", msg);
+                Err(Error::new(ErrorKind::InvalidInput, msg))
+            }
+        }
+    }
+
+    fn with_var(&self, name: &str, value: &str) -> ServiceValues {
+        let mut values = self.clone();
+        values.vars.insert(name.to_string(), serde_json::Value::String(value.to_string()));
+        values
+    }
+}
+
+/// A service whose operations are implemented as Ansible playbooks.
+pub struct PlaybookService {
+    name: String,
+    description: String,
+    dependencies: Vec<String>,
+    variables: Vec<ServiceVariable>,
+    deploy_playbooks: Vec<AnsiblePlaybook>,
+    delete_playbooks: Vec<AnsiblePlaybook>,
+    status_playbooks: Vec<AnsiblePlaybook>,
+    upgrade_playbooks: Vec<AnsiblePlaybook>,
+    join_playbooks: Vec<AnsiblePlaybook>,
+    leave_playbooks: Vec<AnsiblePlaybook>
+}
+
+impl PlaybookService {
+    pub fn new(name: &str, description: &str) -> Self {
+        PlaybookService {
+            name: name.to_string(),
+            description: description.to_string(),
+            dependencies: Vec::new(),
+            variables: Vec::new(),
+            deploy_playbooks: Vec::new(),
+            delete_playbooks: Vec::new(),
+            status_playbooks: Vec::new(),
+            upgrade_playbooks: Vec::new(),
+            join_playbooks: Vec::new(),
+            leave_playbooks: Vec::new()
+        }
+    }
+
+    pub fn with_dependency(mut self, service: &str) -> Self {
+        self.dependencies.push(service.to_string());
+        self
+    }
+
+    pub fn with_variable(mut self, name: &str, default: Option<&str>, description: &str) -> Self {
+        self.variables.push(ServiceVariable {
+            name: name.to_string(),
+            description: description.to_string(),
+            default: default.map(String::from),
+            file: false
+        });
+        self
+    }
+
+    pub fn with_file_variable(mut self, name: &str, description: &str) -> Self {
+        self.variables.push(ServiceVariable {
+            name: name.to_string(),
+            description: description.to_string(),
+            default: None,
+            file: true
+        });
+        self
+    }
+
+    pub fn with_deploy_playbook(mut self, playbook: AnsiblePlaybook) -> Self {
+        self.deploy_playbooks.push(playbook);
+        self
+    }
+
+    pub fn with_delete_playbook(mut self, playbook: AnsiblePlaybook) -> Self {
+        self.delete_playbooks.push(playbook);
+        self
+    }
+
+    pub fn with_status_playbook(mut self, playbook: AnsiblePlaybook) -> Self {
+        self.status_playbooks.push(playbook);
+        self
+    }
+
+    pub fn with_upgrade_playbook(mut self, playbook: AnsiblePlaybook) -> Self {
+        self.upgrade_playbooks.push(playbook);
+        self
+    }
+
+    pub fn with_join_playbook(mut self, playbook: AnsiblePlaybook) -> Self {
+        self.join_playbooks.push(playbook);
+        self
+    }
+
+    pub fn with_leave_playbook(mut self, playbook: AnsiblePlaybook) -> Self {
+        self.leave_playbooks.push(playbook);
+        self
+    }
+
+    fn aggregate_playbook(&self, values: &ServiceValues, playbooks: &[AnsiblePlaybook]) -> AnsibleAggregatePlaybook {
+        let mut aggregate = AnsibleAggregatePlaybook::new();
+        for playbook in playbooks {
+            aggregate.add_playbook(playbook.clone());
+        }
+        for file in &values.files {
+            aggregate.add_extra_vars_file(file);
+        }
+        aggregate.add_extra_vars(&values.vars);
+
+        aggregate
+    }
+
+    fn run_playbooks(
+        &self,
+        settings: &ClusterSettings,
+        executor: &dyn Executor,
+        values: &ServiceValues,
+        operation: &str,
+        playbooks: &[AnsiblePlaybook]) -> Result<ExitStatus, Error> {
+        if playbooks.is_empty() {
+            return Err(unsupported_operation(&self.name, operation));
+        }
+
+        executor.run_playbook(settings, &self.aggregate_playbook(values, playbooks))
+    }
+
+    /// Runs the playbooks of an operation that deploys or removes the service
+    /// on some hosts, recording it on them once the playbooks succeed.
+    fn run_recorded_playbooks(
+        &self,
+        settings: &ClusterSettings,
+        executor: &dyn Executor,
+        values: &ServiceValues,
+        operation: &str,
+        playbooks: &[AnsiblePlaybook],
+        deployed: bool) -> Result<ExitStatus, Error> {
+        if playbooks.is_empty() {
+            return Err(unsupported_operation(&self.name, operation));
+        }
+
+        let mut aggregate = self.aggregate_playbook(values, playbooks);
+        state::add_record(&mut aggregate, &self.name, deployed);
+        executor.run_playbook(settings, &aggregate)
+    }
+}
+
+impl Service for PlaybookService {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn dependencies(&self) -> Vec<String> {
+        self.dependencies.clone()
+    }
+
+    fn variables(&self) -> Vec<ServiceVariable> {
+        self.variables.clone()
+    }
+
+    fn deploy(&self, settings: &ClusterSettings, executor: &dyn Executor, values: &ServiceValues) -> Result<ExitStatus, Error> {
+        self.run_recorded_playbooks(settings, executor, values, "deploy", &self.deploy_playbooks, true)
+    }
+
+    fn delete(&self, settings: &ClusterSettings, executor: &dyn Executor, values: &ServiceValues) -> Result<ExitStatus, Error> {
+        self.run_recorded_playbooks(settings, executor, values, "delete", &self.delete_playbooks, false)
+    }
+
+    /// Runs the status playbooks and shows the health of the service on each
+    /// host, failing if any part of it is degraded.
+    fn status(&self, settings: &ClusterSettings, executor: &dyn Executor, values: &ServiceValues) -> Result<ExitStatus, Error> {
+        if self.status_playbooks.is_empty() {
+            return Err(unsupported_operation(&self.name, "status"));
+        }
+
+        let playbook = self.aggregate_playbook(values, &self.status_playbooks);
+        let (status, results) = executor.run_playbook_with_results(settings, &playbook)?;
+        if settings.dry_run {
+            return Ok(status);
+        }
+
+        let health = ServiceHealth::from_results(&self.name, &results);
+        output::print_records(settings.output.unwrap_or(OutputFormat::Table), &health)?;
+
+        let degraded = health.iter().filter(|h| !h.healthy).count();
+        if degraded > 0 {
+            warn!("Service '{}' is degraded, {} component(s) are not healthy", self.name, degraded);
+        }
+
+        Ok(if status.success() { exit_status(degraded == 0) } else { status })
+    }
+
+    fn upgrade(&self, settings: &ClusterSettings, executor: &dyn Executor, values: &ServiceValues) -> Result<ExitStatus, Error> {
+        self.run_playbooks(settings, executor, values, "upgrade", &self.upgrade_playbooks)
+    }
+
+    fn join(&self, settings: &ClusterSettings, executor: &dyn Executor, values: &ServiceValues) -> Result<ExitStatus, Error> {
+        self.run_recorded_playbooks(settings, executor, values, "join", &self.join_playbooks, true)
+    }
+
+    fn leave(&self, settings: &ClusterSettings, executor: &dyn Executor, values: &ServiceValues) -> Result<ExitStatus, Error> {
+        self.run_recorded_playbooks(settings, executor, values, "leave", &self.leave_playbooks, false)
+    }
+}
+
+/// The set of services known to clusterctl.
+pub struct ServiceRegistry {
+    services: Vec<Box<dyn Service>>
+}
+
+impl ServiceRegistry {
+    /// Creates a registry with the services shipped with clusterctl.
+    pub fn with_builtin_services() -> ServiceRegistry {
+        ServiceRegistry {
+            services: builtin::services()
+        }
+    }
+
+    /// Creates a registry with the built-in services and the ones found in
+    /// the configured services directory.
+    pub fn from_settings(settings: &ClusterSettings) -> Result<ServiceRegistry, Error> {
+        ServiceRegistry::with_services_dir(settings.services_dir.as_deref())
+    }
+
+    /// Creates a registry with the built-in services and the ones found in
+    /// `services_dir`, if any.
+    pub fn with_services_dir(services_dir: Option<&str>) -> Result<ServiceRegistry, Error> {
+        let mut registry = ServiceRegistry::with_builtin_services();
+        if let Some(dir) = services_dir {
+            for service in manifest::load_services(Path::new(dir))? {
+                registry.register(Box::new(service))?;
+            }
+        }
+
+        Ok(registry)
+    }
+
+    pub fn register(&mut self, service: Box<dyn Service>) -> Result<(), Error> {
+        if self.services.iter().any(|s| s.name() == service.name()) {
+            let msg = format!("Service '{}' is already defined", service.name());
+            error!("{}", msg);
+            return Err(Error::other(msg));
+        }
+
+        self.services.push(service);
+        Ok(())
+    }
+
+    pub fn services(&self) -> &[Box<dyn Service>] {
+        &self.services
+    }
+
+    /// Returns the services with the ones they depend on, each of them after
+    /// its dependencies, failing if there are circular dependencies.
+    pub fn deploy_order(&self, names: &[&str]) -> Result<Vec<&dyn Service>, Error> {
+        let mut services: Vec<&dyn Service> = Vec::new();
+        for name in names {
+            self.add_with_dependencies(name, &mut Vec::new(), &mut services)?;
+        }
+
+        Ok(services)
+    }
+
+    /// Returns the services to delete, each of them before the ones it
+    /// depends on. Dependencies are only deleted with `with_dependencies`, and
+    /// only if no other `installed` service needs them, while a service asked
+    /// for that is still needed is an error.
+    pub fn delete_order(&self, names: &[&str], with_dependencies: bool, installed: &[String]) -> Result<Vec<&dyn Service>, Error> {
+        let candidates: Vec<&dyn Service> = self.deploy_order(names)?.into_iter()
+            .filter(|s| with_dependencies || names.contains(&s.name()))
+            .collect();
+
+        let mut remaining: Vec<String> = installed.to_vec();
+        let mut services: Vec<&dyn Service> = Vec::new();
+        for service in candidates.into_iter().rev() {
+            remaining.retain(|s| s != service.name());
+            let needed_by: Vec<&str> = remaining.iter()
+                .filter_map(|s| self.services.iter().find(|r| r.name() == s))
+                .filter(|s| s.dependencies().iter().any(|d| d == service.name()))
+                .map(|s| s.name())
+                .collect();
+
+            if needed_by.is_empty() {
+                services.push(service);
+            } else if names.contains(&service.name()) {
+                let msg = format!("Service '{}' is still needed by '{}', delete it first", service.name(), needed_by.join("', '"));
+                error!("{}", msg);
+                return Err(Error::other(msg));
+            } else {
+                info!("Keeping service '{}', which is still needed by '{}'", service.name(), needed_by.join("', '"));
+                remaining.push(service.name().to_string());
+            }
+        }
+
+        Ok(services)
+    }
+
+    fn add_with_dependencies<'a>(&'a self, name: &str, visiting: &mut Vec<String>, services: &mut Vec<&'a dyn Service>) -> Result<(), Error> {
+        if services.iter().any(|s| s.name() == name) {
+            return Ok(());
+        }
+        if let Some(i) = visiting.iter().position(|v| v == name) {
+            let mut cycle = visiting[i..].to_vec();
+            cycle.push(name.to_string());
+            let msg = format!("Circular dependency between services: {}", cycle.join(" -> "));
+            error!("{}", msg);
+            return Err(Error::new(ErrorKind::InvalidData, msg));
+        }
+
+        let service = self.get(name)?;
+        visiting.push(name.to_string());
+        for dependency in service.dependencies() {
+            self.add_with_dependencies(&dependency, visiting, services)?;
+        }
+        visiting.pop();
+        services.push(service);
+
+        Ok(())
+    }
+
+    /// Looks up a service by name, suggesting similar names if it's unknown.
+    pub fn get(&self, name: &str) -> Result<&dyn Service, Error> {
+        if let Some(service) = self.services.iter().find(|s| s.name() == name) {
+            return Ok(service.as_ref());
+        }
+
+        let names: Vec<&str> = self.services.iter().map(|s| s.name()).collect();
+        let msg = match similar_names(name, &names).as_slice() {
+            [] => format!("Unknown service '{}', run 'clusterctl service list-available' to see the available services", name),
+            [similar] => format!("Unknown service '{}'. Did you mean '{}'?", name, similar),
+            similar => format!("Unknown service '{}'. Did you mean one of '{}'?", name, similar.join("', '"))
+        };
+        error!("{}", msg);
+        Err(Error::other(msg))
+    }
+}
+
+/// Describes the variables of every service, for the command line help.
+pub fn variables_help(registry: &ServiceRegistry) -> String {
+    let mut text = String::from("SERVICE VARIABLES:\n");
+    for service in registry.services() {
+        let variables = service.variables();
+        if variables.is_empty() {
+            continue;
+        }
+
+        text.push_str(&format!("    {}:\n", service.name()));
+        for variable in &variables {
+            text.push_str(&format!("        {}: {}\n", variable.name, variable.description));
+            if let Some(default) = &variable.default {
+                text.push_str(&format!("            default: {}\n", default));
+            }
+        }
+    }
+
+    text
+}
+
+/// Returns the names that look like a misspelling of `name`, most similar first.
+fn similar_names<'a>(name: &str, names: &[&'a str]) -> Vec<&'a str> {
+    let mut scored: Vec<(f64, &str)> = names.iter()
+        .map(|n| (strsim::jaro_winkler(name, n), *n))
+        .filter(|(score, _)| *score >= 0.8)
+        .collect();
+    scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    scored.into_iter().map(|(_, n)| n).collect()
+}
+
+/// Resolves the local file given for the `variable` file variable.
+fn absolute_path(variable: &str, path: &str) -> Result<String, Error> {
+    match fs::canonicalize(path) {
+        Ok(p) => Ok(p.to_string_lossy().to_string()),
+        Err(e) => {
+            let msg = format!("File {} given for '{}' can't be read: {}", path, variable, e);
+            error!("{}", msg);
+            Err(Error::new(e.kind(), msg))
+        }
+    }
+}
+
+fn unsupported_operation(service: &str, operation: &str) -> Error {
+    let msg = format!("Service '{}' doesn't support the '{}' operation", service, operation);
+    error!("{}", msg);
+    Error::other(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use rstest::rstest;
+    use super::{similar_names, variables_help, PlaybookService, Service, ServiceRegistry, ServiceValues};
+
+    #[rstest]
+    #[case("docker", true)]
+    #[case("kubernetes", true)]
+    #[case("k3s", true)]
+    #[case("docker-swarm", true)]
+    #[case("dokcer", false)]
+    fn services_are_looked_up_by_name(#[case] name: &str, #[case] expected_found: bool) {
+        let registry = ServiceRegistry::with_builtin_services();
+        assert_eq!(registry.get(name).is_ok(), expected_found);
+    }
+
+    #[rstest]
+    #[case("dokcer", vec!["docker"])]
+    #[case("kubernets", vec!["kubernetes"])]
+    #[case("nfs", vec![])]
+    fn similar_service_names_are_suggested(#[case] name: &str, #[case] expected: Vec<&str>) {
+        assert_eq!(similar_names(name, &["docker", "kubernetes"]), expected);
+    }
+
+    #[rstest]
+    fn unknown_service_errors_suggest_similar_names() {
+        let registry = ServiceRegistry::with_builtin_services();
+        let error = registry.get("kubernets").err().unwrap();
+        assert_eq!(error.to_string(), "Unknown service 'kubernets'. Did you mean 'kubernetes'?");
+    }
+
+    #[rstest]
+    #[case("kubernetes", vec!["containerd", "kubernetes"])]
+    #[case("nfs-client", vec!["containerd", "docker", "nfs-client"])]
+    #[case("docker", vec!["docker"])]
+    #[case("docker-swarm", vec!["docker", "docker-swarm"])]
+    fn dependencies_are_deployed_first(#[case] name: &str, #[case] expected: Vec<&str>) {
+        let mut registry = ServiceRegistry::with_builtin_services();
+        registry.register(Box::new(PlaybookService::new("nfs-client", "NFS client")
+            .with_dependency("containerd")
+            .with_dependency("docker")
+            .with_dependency("containerd"))).unwrap();
+
+        let services = registry.deploy_order(&[name]).unwrap();
+        assert_eq!(names(&services), expected);
+    }
+
+    #[rstest]
+    fn several_services_are_deployed_in_dependency_order() {
+        let mut registry = ServiceRegistry::with_builtin_services();
+        registry.register(Box::new(PlaybookService::new("monitoring", "Monitoring").with_dependency("kubernetes"))).unwrap();
+
+        let services = registry.deploy_order(&["monitoring", "podman", "kubernetes"]).unwrap();
+        assert_eq!(names(&services), vec!["containerd", "kubernetes", "monitoring", "podman"]);
+    }
+
+    #[rstest]
+    fn circular_dependencies_are_reported() {
+        let mut registry = ServiceRegistry::with_builtin_services();
+        registry.register(Box::new(PlaybookService::new("a", "A").with_dependency("b"))).unwrap();
+        registry.register(Box::new(PlaybookService::new("b", "B").with_dependency("c"))).unwrap();
+        registry.register(Box::new(PlaybookService::new("c", "C").with_dependency("b"))).unwrap();
+
+        let error = registry.deploy_order(&["a"]).err().unwrap();
+        assert_eq!(error.to_string(), "Circular dependency between services: b -> c -> b");
+    }
+
+    #[rstest]
+    #[case(vec!["kubernetes"], false, vec!["containerd", "kubernetes"], Some(vec!["kubernetes"]))]
+    #[case(vec!["kubernetes"], true, vec!["containerd", "kubernetes"], Some(vec!["kubernetes", "containerd"]))]
+    #[case(vec!["kubernetes"], true, vec!["containerd", "kubernetes", "nfs-client"], Some(vec!["kubernetes"]))]
+    #[case(vec!["containerd"], false, vec!["containerd", "kubernetes"], None)]
+    #[case(vec!["containerd", "kubernetes"], false, vec!["containerd", "kubernetes"], Some(vec!["kubernetes", "containerd"]))]
+    fn services_still_needed_are_not_deleted(
+        #[case] requested: Vec<&str>,
+        #[case] with_dependencies: bool,
+        #[case] installed: Vec<&str>,
+        #[case] expected: Option<Vec<&str>>) {
+        let mut registry = ServiceRegistry::with_builtin_services();
+        registry.register(Box::new(PlaybookService::new("nfs-client", "NFS client").with_dependency("containerd"))).unwrap();
+        let installed: Vec<String> = installed.iter().map(|s| s.to_string()).collect();
+
+        let result = registry.delete_order(&requested, with_dependencies, &installed);
+        assert_eq!(result.ok().map(|s| names(&s)), expected);
+    }
+
+    fn names<'a>(services: &[&'a dyn Service]) -> Vec<&'a str> {
+        services.iter().map(|s| s.name()).collect()
+    }
+
+    #[rstest]
+    fn services_cant_be_defined_twice() {
+        let mut registry = ServiceRegistry::with_builtin_services();
+        let builtin_count = registry.services().len();
+
+        assert!(registry.register(Box::new(PlaybookService::new("docker", "Another Docker"))).is_err());
+        assert!(registry.register(Box::new(PlaybookService::new("nfs-client", "NFS client"))).is_ok());
+        assert_eq!(registry.services().len(), builtin_count + 1);
+    }
+
+    #[rstest]
+    #[case(vec![("pod_network_cidr", "10.10.0.0/16")], true)]
+    #[case(vec![("pod_network", "10.10.0.0/16")], false)]
+    #[case(vec![], true)]
+    fn only_declared_variables_can_be_set(#[case] values: Vec<(&str, &str)>, #[case] expected_valid: bool) {
+        let service = PlaybookService::new("kubernetes", "Kubernetes")
+            .with_variable("pod_network_cidr", Some("10.244.0.0/16"), "Pod network CIDR");
+        let values: Vec<(String, String)> = values.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+
+        let result = ServiceValues::new(&[&service], &values, &[]);
+        assert_eq!(result.is_ok(), expected_valid);
+        if let Ok(v) = result {
+            assert_eq!(v.vars.len(), values.len());
+        }
+    }
+
+    #[rstest]
+    #[case("Cargo.toml", true)]
+    #[case("./playbooks/../Cargo.toml", true)]
+    #[case("nonexistent.yaml", false)]
+    fn file_variables_are_made_absolute(#[case] path: &str, #[case] expected_valid: bool) {
+        let service = PlaybookService::new("kubernetes", "Kubernetes")
+            .with_file_variable("cni_manifest_file", "Local manifest of the network add-on");
+        let values = vec![("cni_manifest_file".to_string(), path.to_string())];
+
+        let result = ServiceValues::new(&[&service], &values, &[]);
+        assert_eq!(result.is_ok(), expected_valid);
+        if let Ok(v) = result {
+            let expected = fs::canonicalize("Cargo.toml").unwrap();
+            assert_eq!(v.vars.get("cni_manifest_file").and_then(|v| v.as_str()), expected.to_str());
+        }
+    }
+
+    #[rstest]
+    #[case(None, None)]
+    #[case(Some("1.22.2"), Some("1.22.2"))]
+    fn upgrade_version_is_passed_to_playbooks(#[case] version: Option<&str>, #[case] expected: Option<&str>) {
+        let values = ServiceValues::default().with_version(version);
+        assert_eq!(values.vars.get("service_version").and_then(|v| v.as_str()), expected);
+    }
+
+    #[rstest]
+    #[case(Some("worker3:worker4"), true)]
+    #[case(None, false)]
+    fn target_hosts_are_required(#[case] host_pattern: Option<&str>, #[case] expected_valid: bool) {
+        let result = ServiceValues::default().with_target_hosts(host_pattern, "join");
+        assert_eq!(result.is_ok(), expected_valid);
+        if let Ok(values) = result {
+            assert_eq!(values.vars.get("target_hosts").and_then(|v| v.as_str()), host_pattern);
+        }
+    }
+
+    #[rstest]
+    fn missing_values_files_are_rejected() {
+        let service = PlaybookService::new("docker", "Docker");
+        assert!(ServiceValues::new(&[&service], &[], &["/nonexistent/values.yaml".to_string()]).is_err());
+    }
+
+    #[rstest]
+    fn variables_are_described_in_help() {
+        let mut registry = ServiceRegistry::with_builtin_services();
+        registry.register(Box::new(PlaybookService::new("nfs-client", "NFS client")
+            .with_variable("nfs_server", None, "NFS server address")
+            .with_variable("mount_point", Some("/mnt/nfs"), "Where to mount the share"))).unwrap();
+
+        assert!(variables_help(&registry).ends_with("    nfs-client:
+        nfs_server: NFS server address
+        mount_point: Where to mount the share
+            default: /mnt/nfs
+"));
+    }
+}
@@ -0,0 +1,108 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::io::Error;
+
+use crate::commands::ansible::{AnsibleAggregatePlaybook, AnsibleCommand, AnsiblePlaybook};
+use crate::commands::executor::Executor;
+use crate::commands::results::HostResult;
+use crate::commands::RECORD_SERVICE_COMMAND_PLAYBOOK;
+use crate::utils::settings::ClusterSettings;
+
+/// Directory on the machines with a marker file for every service deployed on them.
+const SERVICES_STATE_DIR: &str = "/etc/clusterctl/services";
+
+/// Services deployed on each of the machines that could be checked.
+#[derive(Debug, Default)]
+pub struct InstalledServices {
+    hosts: Vec<(String, Vec<String>)>
+}
+
+impl InstalledServices {
+    /// Reads the service markers from the machines matched by `host_pattern`.
+    pub fn load(settings: &ClusterSettings, executor: &dyn Executor, host_pattern: &str) -> Result<InstalledServices, Error> {
+        let command = AnsibleCommand::new("shell", false, Some(host_pattern.to_string()))
+            .with_parameter(&format!("ls -1 {} 2>/dev/null || true", SERVICES_STATE_DIR), "");
+        let (_, results) = executor.run_module_with_results(settings, &command)?;
+
+        Ok(InstalledServices::from_results(&results))
+    }
+
+    /// Extracts the services listed on each host, ignoring the ones that
+    /// couldn't be reached.
+    pub fn from_results(results: &[HostResult]) -> InstalledServices {
+        let hosts = results.iter()
+            .filter(|r| r.is_success())
+            .map(|r| (r.host.clone(), r.stdout.lines().map(|l| l.trim().to_string()).filter(|l| !l.is_empty()).collect()))
+            .collect();
+
+        InstalledServices { hosts }
+    }
+
+    /// Whether the service is deployed on all the machines checked.
+    pub fn is_installed_everywhere(&self, service: &str) -> bool {
+        !self.hosts.is_empty() && self.hosts.iter().all(|(_, services)| services.iter().any(|s| s == service))
+    }
+
+    /// Names of the services deployed on any of the machines checked.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.hosts.iter().flat_map(|(_, services)| services.clone()).collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+/// Adds a last playbook recording the service as deployed, or removing its
+/// record, on the hosts in `target_hosts`, or the whole cluster. Hosts that
+/// failed in the previous playbooks are left as they were.
+pub fn add_record(playbook: &mut AnsibleAggregatePlaybook, service: &str, deployed: bool) {
+    playbook.add_playbook(AnsiblePlaybook::load(RECORD_SERVICE_COMMAND_PLAYBOOK));
+
+    let mut vars = serde_json::Map::new();
+    vars.insert("services_state_dir".to_string(), serde_json::Value::from(SERVICES_STATE_DIR));
+    vars.insert("recorded_service".to_string(), serde_json::Value::from(service));
+    vars.insert("service_deployed".to_string(), serde_json::Value::from(deployed));
+    playbook.add_extra_vars(&vars);
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use crate::commands::results::{HostResult, HostStatus};
+    use super::InstalledServices;
+
+    fn host_result(host: &str, status: HostStatus, stdout: &str) -> HostResult {
+        HostResult {
+            host: host.to_string(),
+            status,
+            rc: Some(0),
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+            msg: String::new(),
+            facts: serde_json::Value::Null
+        }
+    }
+
+    #[rstest]
+    fn installed_services_are_read_from_markers() {
+        let installed = InstalledServices::from_results(&[
+            host_result("master1", HostStatus::Changed, "containerd\nkubernetes\n"),
+            host_result("worker1", HostStatus::Changed, "containerd\n"),
+            host_result("worker2", HostStatus::Unreachable, "")
+        ]);
+
+        assert!(installed.is_installed_everywhere("containerd"));
+        assert!(!installed.is_installed_everywhere("kubernetes"));
+        assert_eq!(installed.names(), vec!["containerd", "kubernetes"]);
+    }
+
+    #[rstest]
+    fn nothing_is_installed_without_reachable_hosts() {
+        let installed = InstalledServices::from_results(&[host_result("worker2", HostStatus::Unreachable, "")]);
+        assert!(!installed.is_installed_everywhere("containerd"));
+    }
+}
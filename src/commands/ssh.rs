@@ -0,0 +1,470 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::fs;
+use std::io::{self, Error, ErrorKind, Write};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
+use std::thread;
+
+use log::{error, info};
+
+use crate::commands::executor::Executor;
+use crate::commands::process::{self, shell_quote};
+use crate::commands::results::{HostResult, HostStatus};
+use crate::utils::inventory::{Inventory, ALL_GROUP};
+use crate::utils::output;
+use crate::utils::settings::ClusterSettings;
+
+/// Maximum number of machines contacted at the same time, as Ansible's forks.
+const MAX_PARALLEL_CONNECTIONS: usize = 5;
+
+/// Exit code returned by ssh when the connection fails.
+const SSH_CONNECTION_ERROR: i32 = 255;
+
+/// How to connect to a host, from its inventory variables.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SshConnection {
+    pub host: String,
+    pub address: String,
+    pub user: Option<String>,
+    pub port: Option<String>,
+    pub identity_file: Option<String>
+}
+
+impl SshConnection {
+    pub fn from_inventory(inventory: &Inventory, host: &str) -> SshConnection {
+        SshConnection {
+            host: host.to_string(),
+            address: inventory.host_var(host, "ansible_host").unwrap_or_else(|| host.to_string()),
+            user: inventory.host_var(host, "ansible_user"),
+            port: inventory.host_var(host, "ansible_port"),
+            identity_file: inventory.host_var(host, "ansible_ssh_private_key_file")
+        }
+    }
+
+    /// Arguments for an interactive shell session on the host.
+    pub fn interactive_arguments(&self) -> Vec<String> {
+        let mut args = self.connection_options("-p");
+        args.push(self.destination(&self.address));
+
+        args
+    }
+
+    /// Arguments for running a command on the host without user interaction.
+    pub fn ssh_arguments(&self, command: &str) -> Vec<String> {
+        let mut args = self.options("-p");
+        args.push(self.destination(&self.address));
+        args.push("--".to_string());
+        args.push(command.to_string());
+
+        args
+    }
+
+    /// Arguments for copying a local file to the host.
+    pub fn upload_arguments(&self, src: &str, dest: &str) -> Vec<String> {
+        let mut args = self.options("-P");
+        args.push(src.to_string());
+        args.push(format!("{}:{}", self.destination(&self.scp_address()), dest));
+
+        args
+    }
+
+    /// Arguments for copying a file on the host to the local machine.
+    pub fn download_arguments(&self, src: &str, dest: &str) -> Vec<String> {
+        let mut args = self.options("-P");
+        args.push(format!("{}:{}", self.destination(&self.scp_address()), src));
+        args.push(dest.to_string());
+
+        args
+    }
+
+    fn options(&self, port_flag: &str) -> Vec<String> {
+        let mut args: Vec<String> = vec![
+            "-o".to_string(), "BatchMode=yes".to_string(),
+            "-o".to_string(), "ConnectTimeout=10".to_string()
+        ];
+        args.extend(self.connection_options(port_flag));
+
+        args
+    }
+
+    fn connection_options(&self, port_flag: &str) -> Vec<String> {
+        let mut args: Vec<String> = Vec::new();
+
+        if let Some(p) = &self.port {
+            args.push(port_flag.to_string());
+            args.push(p.clone());
+        }
+        if let Some(i) = &self.identity_file {
+            args.push("-i".to_string());
+            args.push(i.clone());
+        }
+
+        args
+    }
+
+    fn destination(&self, address: &str) -> String {
+        match &self.user {
+            Some(u) => format!("{}@{}", u, address),
+            None => address.to_string()
+        }
+    }
+
+    fn scp_address(&self) -> String {
+        if self.address.contains(':') {
+            format!("[{}]", self.address)
+        } else {
+            self.address.clone()
+        }
+    }
+}
+
+/// Executor running commands through the system's `ssh` and `scp` binaries,
+/// without needing Ansible on the control machine.
+pub struct SshExecutor {
+    inventory: Inventory
+}
+
+impl SshExecutor {
+    pub fn new(inventory: Inventory) -> SshExecutor {
+        SshExecutor { inventory }
+    }
+
+    fn connections(&self, settings: &ClusterSettings) -> Result<Vec<SshConnection>, Error> {
+        let pattern = settings.host_pattern.as_deref().unwrap_or(ALL_GROUP);
+        let hosts = self.inventory.resolve_pattern(pattern)?;
+        if hosts.is_empty() {
+            let msg = format!("No hosts in the inventory match '{}'", pattern);
+            error!("{}", msg);
+            return Err(Error::other(msg));
+        }
+
+        Ok(hosts.iter().map(|h| SshConnection::from_inventory(&self.inventory, &h.name)).collect())
+    }
+
+    /// Runs a task on all the matched machines in parallel and reports the results.
+    fn run_task<F>(&self, settings: &ClusterSettings, task: F) -> Result<ExitStatus, Error>
+        where F: Fn(&SshConnection) -> HostResult + Sync {
+        let connections = self.connections(settings)?;
+        let mut results = Vec::new();
+        let task = &task;
+
+        for batch in connections.chunks(MAX_PARALLEL_CONNECTIONS) {
+            thread::scope(|scope| {
+                let handles: Vec<_> = batch.iter()
+                    .map(|c| scope.spawn(move || task(c)))
+                    .collect();
+                for (connection, handle) in batch.iter().zip(handles) {
+                    results.push(handle.join().unwrap_or_else(|_| {
+                        host_result(&connection.host, Err(Error::other("Task panicked")), HostStatus::Changed)
+                    }));
+                }
+            });
+        }
+
+        match settings.output {
+            _ if settings.dry_run => {},
+            Some(format) => output::print_records(format, &results)?,
+            None => print_results(&results)?
+        }
+
+        Ok(exit_status(&results))
+    }
+}
+
+impl Executor for SshExecutor {
+    fn ping(&self, settings: &ClusterSettings) -> Result<ExitStatus, Error> {
+        self.run_task(settings, |c| {
+            let mut result = host_result(&c.host, execute(settings, "ssh", &c.ssh_arguments("true")), HostStatus::Ok);
+            if result.is_success() {
+                result.msg = "pong".to_string();
+            }
+            result
+        })
+    }
+
+    fn run(&self, settings: &ClusterSettings, command: &str, needs_become: bool, chdir: Option<String>) -> Result<ExitStatus, Error> {
+        let remote_command = remote_command(command, needs_become, chdir.as_deref());
+        self.run_task(settings, |c| host_result(&c.host, execute(settings, "ssh", &c.ssh_arguments(&remote_command)), HostStatus::Changed))
+    }
+
+    fn copy(&self, settings: &ClusterSettings, src: &str, dest: &str) -> Result<ExitStatus, Error> {
+        self.run_task(settings, |c| host_result(&c.host, execute(settings, "scp", &c.upload_arguments(src, dest)), HostStatus::Changed))
+    }
+
+    fn fetch(&self, settings: &ClusterSettings, src: &str, dest: &str) -> Result<ExitStatus, Error> {
+        self.run_task(settings, |c| {
+            let local_path = fetch_destination(dest, &c.host, src);
+            if let Some(parent) = local_path.parent().filter(|_| !settings.dry_run) {
+                if let Err(e) = fs::create_dir_all(parent) {
+                    return host_result(&c.host, Err(e), HostStatus::Changed);
+                }
+            }
+
+            let mut result = host_result(&c.host, execute(settings, "scp", &c.download_arguments(src, &local_path.to_string_lossy())), HostStatus::Changed);
+            if result.is_success() {
+                result.msg = format!("Fetched to {}", local_path.display());
+            }
+            result
+        })
+    }
+}
+
+/// Replaces the current process with an interactive `ssh` session to a host
+/// in the inventory. Hosts can be given by a partial name, and the user is
+/// asked to pick one when several of them match.
+pub fn run_interactive_ssh(settings: &ClusterSettings, query: Option<&str>) -> Result<ExitStatus, Error> {
+    let inventory = Inventory::from_settings(settings)?;
+    let pattern = settings.host_pattern.as_deref().unwrap_or(ALL_GROUP);
+    let names: Vec<&str> = inventory.resolve_pattern(pattern)?.iter()
+        .map(|h| h.name.as_str())
+        .collect();
+
+    let candidates = match query {
+        Some(q) => fuzzy_match(&names, q),
+        None => names
+    };
+    let host = match candidates.len() {
+        0 => {
+            let msg = format!("No hosts in the inventory match '{}'", query.unwrap_or(pattern));
+            error!("{}", msg);
+            return Err(Error::other(msg));
+        },
+        1 => candidates[0],
+        _ => pick_host(&inventory, &candidates)?
+    };
+
+    let mut command = Command::new("ssh");
+    command.args(SshConnection::from_inventory(&inventory, host).interactive_arguments());
+    if settings.dry_run {
+        process::print_command(&command)?;
+        return Ok(ExitStatus::from_raw(0));
+    }
+
+    info!("Executing {}", process::command_line(&command));
+    Err(command.exec())
+}
+
+/// Finds the hosts matching a partial name. An exact match wins, then names
+/// starting with the query, containing it, or containing its characters in
+/// the same order.
+fn fuzzy_match<'a>(names: &[&'a str], query: &str) -> Vec<&'a str> {
+    if let Some(name) = names.iter().find(|n| **n == query) {
+        return vec![name];
+    }
+
+    let query = query.to_lowercase();
+    let is_subsequence = |name: &str| {
+        let mut chars = name.chars();
+        query.chars().all(|q| chars.any(|c| c == q))
+    };
+    let matchers: [&dyn Fn(&str) -> bool; 3] = [
+        &|name| name.starts_with(&query),
+        &|name| name.contains(&query),
+        &is_subsequence
+    ];
+
+    for matcher in matchers.iter() {
+        let matches: Vec<&str> = names.iter()
+            .filter(|n| matcher(&n.to_lowercase()))
+            .copied()
+            .collect();
+        if !matches.is_empty() {
+            return matches;
+        }
+    }
+
+    Vec::new()
+}
+
+/// Asks the user to choose one of several hosts.
+fn pick_host<'a>(inventory: &Inventory, candidates: &[&'a str]) -> Result<&'a str, Error> {
+    let mut stderr = io::stderr();
+    for (i, host) in candidates.iter().enumerate() {
+        match inventory.host_var(host, "ansible_host") {
+            Some(address) => writeln!(stderr, "{:>3}) {} ({})", i + 1, host, address)?,
+            None => writeln!(stderr, "{:>3}) {}", i + 1, host)?
+        }
+    }
+    write!(stderr, "Select a host [1-{}]: ", candidates.len())?;
+    stderr.flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    answer.trim().parse::<usize>().ok()
+        .filter(|n| (1..=candidates.len()).contains(n))
+        .map(|n| candidates[n - 1])
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("Invalid selection '{}'", answer.trim())))
+}
+
+fn execute(settings: &ClusterSettings, program: &str, args: &[String]) -> Result<Output, Error> {
+    process::output(settings, Command::new(program).args(args))
+}
+
+/// Builds the command line run by the remote shell.
+fn remote_command(command: &str, needs_become: bool, chdir: Option<&str>) -> String {
+    let command = match chdir {
+        Some(d) => format!("cd {} && {}", shell_quote(d), command),
+        None => command.to_string()
+    };
+
+    if needs_become {
+        format!("sudo -n sh -c {}", shell_quote(&command))
+    } else {
+        command
+    }
+}
+
+/// Local path a fetched file is stored in, following Ansible's `fetch` layout.
+fn fetch_destination(dest: &str, host: &str, src: &str) -> PathBuf {
+    Path::new(dest).join(host).join(src.trim_start_matches('/'))
+}
+
+fn host_result(host: &str, output: Result<Output, Error>, success_status: HostStatus) -> HostResult {
+    match output {
+        Ok(o) => {
+            let rc = o.status.code();
+            let status = match rc {
+                Some(0) => success_status,
+                Some(SSH_CONNECTION_ERROR) => HostStatus::Unreachable,
+                _ => HostStatus::Failed
+            };
+
+            HostResult {
+                host: host.to_string(),
+                status,
+                rc: rc.map(i64::from),
+                stdout: String::from_utf8_lossy(&o.stdout).trim_end().to_string(),
+                stderr: String::from_utf8_lossy(&o.stderr).trim_end().to_string(),
+                msg: String::new(),
+                facts: serde_json::Value::Null
+            }
+        },
+        Err(e) => HostResult {
+            host: host.to_string(),
+            status: HostStatus::Failed,
+            rc: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            msg: e.to_string(),
+            facts: serde_json::Value::Null
+        }
+    }
+}
+
+/// Prints the results in the same layout as Ansible's ad-hoc commands.
+fn print_results(results: &[HostResult]) -> Result<(), Error> {
+    let mut stdout = io::stdout();
+    for result in results {
+        let status = match result.status {
+            HostStatus::Ok => "SUCCESS".to_string(),
+            other => other.to_string().to_uppercase()
+        };
+        let rc = result.rc.map(|rc| format!(" | rc={}", rc)).unwrap_or_default();
+        writeln!(stdout, "{} | {}{} >>", result.host, status, rc)?;
+
+        let output = result.output();
+        if !output.is_empty() {
+            writeln!(stdout, "{}", output)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Exit status following Ansible's conventions: 2 when a host failed, 4 when
+/// a host couldn't be reached.
+fn exit_status(results: &[HostResult]) -> ExitStatus {
+    let code = if results.iter().any(|r| r.status == HostStatus::Unreachable) {
+        4
+    } else if results.iter().any(|r| r.status == HostStatus::Failed) {
+        2
+    } else {
+        0
+    };
+
+    ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use rstest::rstest;
+    use crate::utils::inventory::Inventory;
+    use super::{fetch_destination, fuzzy_match, remote_command, SshConnection};
+
+    const INVENTORY: &str = "\
+[cluster]
+master1 ansible_host=10.0.0.1 ansible_user=pi ansible_port=2222
+worker1 ansible_ssh_private_key_file=~/.ssh/cluster
+worker2 ansible_host=fe80::2
+";
+
+    #[rstest]
+    fn connections_are_built_from_host_variables() {
+        let inventory = Inventory::parse_ini(INVENTORY, Path::new("hosts")).unwrap();
+
+        let master = SshConnection::from_inventory(&inventory, "master1");
+        assert_eq!(master.ssh_arguments("uptime"), vec![
+            "-o", "BatchMode=yes", "-o", "ConnectTimeout=10", "-p", "2222", "pi@10.0.0.1", "--", "uptime"
+        ]);
+        assert_eq!(master.upload_arguments("a.txt", "/tmp/"), vec![
+            "-o", "BatchMode=yes", "-o", "ConnectTimeout=10", "-P", "2222", "a.txt", "pi@10.0.0.1:/tmp/"
+        ]);
+
+        let worker = SshConnection::from_inventory(&inventory, "worker1");
+        assert_eq!(worker.ssh_arguments("ls"), vec![
+            "-o", "BatchMode=yes", "-o", "ConnectTimeout=10", "-i", "~/.ssh/cluster", "worker1", "--", "ls"
+        ]);
+
+        let ipv6 = SshConnection::from_inventory(&inventory, "worker2");
+        assert_eq!(ipv6.download_arguments("/etc/hosts", "out"), vec![
+            "-o", "BatchMode=yes", "-o", "ConnectTimeout=10", "[fe80::2]:/etc/hosts", "out"
+        ]);
+    }
+
+    #[rstest]
+    fn interactive_sessions_skip_batch_mode() {
+        let inventory = Inventory::parse_ini(INVENTORY, Path::new("hosts")).unwrap();
+        let master = SshConnection::from_inventory(&inventory, "master1");
+
+        assert_eq!(master.interactive_arguments(), vec!["-p", "2222", "pi@10.0.0.1"]);
+    }
+
+    #[rstest]
+    #[case("worker1", vec!["worker1"])]
+    #[case("work", vec!["worker1", "worker10", "worker2"])]
+    #[case("MASTER", vec!["master1"])]
+    #[case("10", vec!["worker10"])]
+    #[case("wk2", vec!["worker2"])]
+    #[case("nas", vec![])]
+    fn host_names_are_fuzzy_matched(
+        #[case] query: &str,
+        #[case] expected_hosts: Vec<&str>) {
+        let names = vec!["master1", "worker1", "worker10", "worker2"];
+        assert_eq!(fuzzy_match(&names, query), expected_hosts);
+    }
+
+    #[rstest]
+    #[case("ls", false, None, "ls")]
+    #[case("ls", false, Some("/tmp"), "cd '/tmp' && ls")]
+    #[case("apt update", true, None, "sudo -n sh -c 'apt update'")]
+    #[case("cat x", true, Some("/srv"), "sudo -n sh -c 'cd '\\''/srv'\\'' && cat x'")]
+    fn remote_commands_are_correctly_built(
+        #[case] command: &str,
+        #[case] needs_become: bool,
+        #[case] chdir: Option<&str>,
+        #[case] expected: &str) {
+        assert_eq!(remote_command(command, needs_become, chdir), expected);
+    }
+
+    #[rstest]
+    fn fetched_files_are_stored_per_host() {
+        assert_eq!(fetch_destination("backup", "master1", "/etc/hosts"), PathBuf::from("backup/master1/etc/hosts"));
+    }
+}
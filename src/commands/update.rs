@@ -0,0 +1,178 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::io::{Error, ErrorKind};
+use std::process::ExitStatus;
+
+use log::error;
+use serde_yaml::{Mapping, Value};
+
+use crate::commands::ansible::{AnsibleAggregatePlaybook, AnsiblePlaybook};
+use crate::commands::executor;
+use crate::commands::results::{PendingUpdate, UpdateResult};
+use crate::commands::{CHECK_UPDATES_COMMAND_PLAYBOOK, REBOOT_HOSTS_COMMAND_PLAYBOOK, UPDATE_COMMAND_PLAYBOOK};
+use crate::utils::inventory::{Inventory, ALL_GROUP};
+use crate::utils::inventory::validate::CLUSTER_MANAGERS_GROUP;
+use crate::utils::output;
+use crate::utils::settings::{ClusterSettings, OutputFormat, UpdateCommand};
+
+/// Updates the packages on the machines with the package manager of each of
+/// them, so that clusters mixing distributions are updated in one go. With
+/// `--check`, only the packages that would be upgraded are listed.
+pub fn run_update(settings: &ClusterSettings, options: &UpdateCommand) -> Result<ExitStatus, Error> {
+    if options.rolling {
+        return run_rolling_update(settings, options);
+    }
+
+    let playbook_contents = if options.check { CHECK_UPDATES_COMMAND_PLAYBOOK } else { UPDATE_COMMAND_PLAYBOOK };
+    let playbook = update_playbook(playbook_contents, settings.host_pattern.as_deref(), &options.exclude);
+    let (status, results) = executor::executor(settings)?.run_playbook_with_results(settings, &playbook)?;
+    if settings.dry_run {
+        return Ok(status);
+    }
+
+    let format = settings.output.unwrap_or(OutputFormat::Table);
+    if options.check {
+        output::print_records(format, &PendingUpdate::from_results(&results))?;
+    } else {
+        output::print_records(format, &UpdateResult::from_results(&results))?;
+    }
+
+    Ok(status)
+}
+
+/// Updates the machines in batches of `--batch` machines, workers first, in a
+/// single playbook run. The machines that need it are rebooted, and drained
+/// before if they are Kubernetes nodes, before moving on to the next batch,
+/// so that the cluster keeps running during the update. Ansible stops the
+/// rollout at the first batch that fails.
+fn run_rolling_update(settings: &ClusterSettings, options: &UpdateCommand) -> Result<ExitStatus, Error> {
+    let batch_size = options.batch.unwrap_or(1);
+    if batch_size == 0 {
+        let msg = "The batch size of a rolling update must be at least 1";
+        error!("{}", msg);
+        return Err(Error::new(ErrorKind::InvalidInput, msg));
+    }
+
+    let inventory = Inventory::from_settings(settings)?;
+    let hosts = rolling_order(&inventory, settings.host_pattern.as_deref().unwrap_or(ALL_GROUP))?.join(",");
+    let playbook = update_playbook(&rolling_update_playbook(batch_size)?, Some(&hosts), &options.exclude);
+    let (status, results) = executor::executor(settings)?.run_playbook_with_results(settings, &playbook)?;
+    if settings.dry_run {
+        return Ok(status);
+    }
+
+    if !status.success() {
+        error!("A batch of the rolling update failed, the machines after it were left untouched");
+    }
+    output::print_records(settings.output.unwrap_or(OutputFormat::Table), &UpdateResult::from_results(&results))?;
+
+    Ok(status)
+}
+
+/// Lists the machines matched by `pattern` with the workers first and the
+/// managers last, so that the control plane is the last to go down.
+pub fn rolling_order(inventory: &Inventory, pattern: &str) -> Result<Vec<String>, Error> {
+    let managers = inventory.group_hosts(CLUSTER_MANAGERS_GROUP);
+    let (mut hosts, manager_hosts): (Vec<_>, Vec<_>) = inventory.resolve_pattern(pattern)?.into_iter()
+        .partition(|h| !managers.iter().any(|m| m.name == h.name));
+    hosts.extend(manager_hosts);
+
+    Ok(hosts.into_iter().map(|h| h.name.clone()).collect())
+}
+
+/// Merges the update and reboot playbooks into a single play that Ansible
+/// runs `batch_size` hosts at a time, rebooting the hosts of each batch that
+/// need it, and stopping as soon as any host of a batch fails.
+fn rolling_update_playbook(batch_size: usize) -> Result<String, Error> {
+    let invalid = |e: serde_yaml::Error| {
+        Error::new(ErrorKind::InvalidData, format!("Could not build the rolling update playbook: {}", e))
+    };
+    let mut play = serde_yaml::from_str::<Vec<Mapping>>(UPDATE_COMMAND_PLAYBOOK).map_err(invalid)?.remove(0);
+    let mut reboot_play = serde_yaml::from_str::<Vec<Mapping>>(REBOOT_HOSTS_COMMAND_PLAYBOOK).map_err(invalid)?.remove(0);
+
+    play.insert(Value::from("serial"), Value::from(batch_size as u64));
+    play.insert(Value::from("max_fail_percentage"), Value::from(0));
+    if let (Some(Value::Mapping(vars)), Some(Value::Mapping(reboot_vars))) =
+        (play.get_mut(&Value::from("vars")), reboot_play.remove(&Value::from("vars"))) {
+        vars.extend(reboot_vars);
+    }
+
+    let mut reboot = Mapping::new();
+    reboot.insert(Value::from("name"), Value::from("Reboot the hosts that need it to finish the update"));
+    reboot.insert(Value::from("when"), Value::from("reboot_required | bool"));
+    reboot.insert(Value::from("block"), reboot_play.remove(&Value::from("tasks")).unwrap_or_default());
+    if let Some(Value::Sequence(tasks)) = play.get_mut(&Value::from("tasks")) {
+        tasks.push(Value::Mapping(reboot));
+    }
+
+    serde_yaml::to_string(&vec![play]).map_err(invalid)
+}
+
+fn update_playbook(contents: &str, host_pattern: Option<&str>, exclude: &[String]) -> AnsibleAggregatePlaybook {
+    let mut playbook = AnsibleAggregatePlaybook::new();
+    playbook.add_playbook(AnsiblePlaybook::load(contents));
+
+    let mut vars = serde_json::Map::new();
+    if let Some(pattern) = host_pattern {
+        vars.insert("target_hosts".to_string(), serde_json::Value::from(pattern));
+    }
+    if !exclude.is_empty() {
+        vars.insert("excluded_packages".to_string(), serde_json::Value::from(exclude.to_vec()));
+    }
+    playbook.add_extra_vars(&vars);
+
+    playbook
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use rstest::rstest;
+    use serde_yaml::{Mapping, Value};
+    use crate::utils::inventory::Inventory;
+    use super::{rolling_order, rolling_update_playbook};
+
+    const INVENTORY: &str = "\
+[cluster_managers]
+master1
+master2
+
+[cluster_workers]
+worker1
+worker2
+worker3
+
+[cluster:children]
+cluster_managers
+cluster_workers
+";
+
+    #[rstest]
+    #[case("all", vec!["worker1", "worker2", "worker3", "master1", "master2"])]
+    #[case("cluster_managers", vec!["master1", "master2"])]
+    #[case("master*:worker2", vec!["worker2", "master1", "master2"])]
+    fn workers_are_updated_before_managers(#[case] pattern: &str, #[case] expected: Vec<&str>) {
+        let inventory = Inventory::parse_ini(INVENTORY, Path::new("inventory")).unwrap();
+        assert_eq!(rolling_order(&inventory, pattern).unwrap(), expected);
+    }
+
+    #[rstest]
+    fn rolling_updates_reboot_each_batch() {
+        let plays: Vec<Mapping> = serde_yaml::from_str(&rolling_update_playbook(2).unwrap()).unwrap();
+        assert_eq!(plays.len(), 1);
+
+        let play = &plays[0];
+        assert_eq!(play[&Value::from("serial")], Value::from(2));
+        assert_eq!(play[&Value::from("max_fail_percentage")], Value::from(0));
+        assert!(play[&Value::from("vars")].get("main_master").is_some());
+        assert!(play[&Value::from("vars")].get("excluded_packages").is_some());
+
+        let reboot = play[&Value::from("tasks")].as_sequence().unwrap().last().unwrap();
+        assert_eq!(reboot["when"], Value::from("reboot_required | bool"));
+        assert!(reboot["block"].as_sequence().unwrap().iter().any(|t| t.get("reboot").is_some()));
+    }
+}
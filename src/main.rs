@@ -6,7 +6,7 @@
 
 use std::io::Error;
 
-use clap::Clap;
+use clap::{App, AppSettings, Clap, ErrorKind, IntoApp};
 use simple_logger::SimpleLogger;
 
 mod utils;
@@ -14,11 +14,51 @@ use utils::settings::ClusterSettings;
 mod commands;
 use commands::CommandRunner;
 
+// Service commands whose help lists the service variables
+const SERVICE_OPERATIONS: [&str; 6] = ["deploy", "delete", "status", "upgrade", "join", "leave"];
+
 fn main() -> Result<(), Error> {
     SimpleLogger::new().init().unwrap();
 
-    let settings: ClusterSettings = ClusterSettings::parse();
-    settings.run()?;
+    let settings = parse_settings();
+    let status = settings.run()?;
+    if !status.success() {
+        std::process::exit(status.code().unwrap_or(1));
+    }
 
     Ok(())
 }
+
+/// Parses the command line. When help is requested, the global options are
+/// parsed on their own first, as the services directory is needed to list
+/// the variables of user-defined services in the help of the service commands.
+fn parse_settings() -> ClusterSettings {
+    let error = match ClusterSettings::try_parse() {
+        Ok(settings) => return settings,
+        Err(e) => e
+    };
+
+    if error.kind == ErrorKind::DisplayHelp {
+        let global_matches = ClusterSettings::into_app()
+            .setting(AppSettings::IgnoreErrors)
+            .get_matches();
+        let help = commands::service_variables_help(global_matches.value_of("services-dir"));
+
+        let mut app = ClusterSettings::into_app();
+        add_service_variables_help(&mut app, &help);
+        if let Err(e) = app.try_get_matches() {
+            e.exit();
+        }
+    }
+
+    error.exit()
+}
+
+/// Lists the variables of every service in the help of the service commands.
+fn add_service_variables_help<'help>(app: &mut App<'help>, help: &'help str) {
+    for service_command in app.get_subcommands_mut().filter(|c| c.get_name() == "service") {
+        for command in service_command.get_subcommands_mut().filter(|c| SERVICE_OPERATIONS.contains(&c.get_name())) {
+            *command = std::mem::take(command).after_help(help);
+        }
+    }
+}
@@ -0,0 +1,622 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::utils::inventory::{expand_host_range, is_yaml_file, ALL_GROUP, Inventory};
+use crate::utils::inventory::ini::{split_host_port, split_line};
+use crate::utils::inventory::yaml::{self, Node};
+
+/// Indentation used for new YAML blocks when it can't be guessed from the file.
+const DEFAULT_YAML_INDENT: usize = 2;
+
+/// Edits an inventory file line by line, so that comments, blank lines and
+/// the order of hosts and groups are kept untouched.
+pub struct InventoryEditor {
+    path: PathBuf,
+    lines: Vec<String>,
+    trailing_newline: bool
+}
+
+/// How a host name appears in an inventory entry.
+#[derive(PartialEq)]
+enum HostMatch {
+    Exact,
+    Range,
+    None
+}
+
+impl InventoryEditor {
+    pub fn open(path: &Path) -> Result<InventoryEditor, Error> {
+        if path.is_dir() {
+            return Err(edit_error(&format!("{} is a directory, only inventory files can be edited", path.display())));
+        }
+
+        let contents = fs::read_to_string(path)?;
+        Ok(InventoryEditor::from_contents(path, &contents))
+    }
+
+    fn from_contents(path: &Path, contents: &str) -> InventoryEditor {
+        InventoryEditor {
+            path: path.to_path_buf(),
+            lines: contents.lines().map(String::from).collect(),
+            trailing_newline: contents.ends_with('\n') || contents.is_empty()
+        }
+    }
+
+    pub fn contents(&self) -> String {
+        let mut contents = self.lines.join("\n");
+        if self.trailing_newline {
+            contents.push('\n');
+        }
+
+        contents
+    }
+
+    /// Parses the inventory as it would be saved.
+    pub fn inventory(&self) -> Result<Inventory, Error> {
+        Inventory::parse(&self.contents(), &self.path)
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        info!("Writing inventory file {}", self.path.display());
+        fs::write(&self.path, self.contents())
+    }
+
+    /// Adds a host with the given address to the inventory, as a member of
+    /// all `groups`.
+    pub fn add_host(&mut self, host: &str, address: &str, groups: &[String]) -> Result<(), Error> {
+        if self.inventory()?.host(host).is_some() {
+            return Err(edit_error(&format!("Host '{}' already exists in the inventory", host)));
+        }
+        if groups.is_empty() {
+            return Err(edit_error(&format!("At least one group is needed to add host '{}'", host)));
+        }
+
+        if is_yaml_file(&self.path) {
+            self.yaml_add_host(host, address, groups)
+        } else {
+            self.ini_add_host(host, address, groups)
+        }
+    }
+
+    /// Removes a host and all its group memberships from the inventory.
+    pub fn remove_host(&mut self, host: &str) -> Result<(), Error> {
+        if self.inventory()?.host(host).is_none() {
+            return Err(edit_error(&format!("Host '{}' does not exist in the inventory", host)));
+        }
+
+        if is_yaml_file(&self.path) {
+            self.yaml_remove_host(host)
+        } else {
+            self.ini_remove_host(host)
+        }
+    }
+
+    /// Moves a host from a group it is directly listed in to another group.
+    pub fn move_host(&mut self, host: &str, from: &str, to: &str) -> Result<(), Error> {
+        let inventory = self.inventory()?;
+        if !inventory.group(from).map(|g| g.has_host(host)).unwrap_or(false) {
+            return Err(edit_error(&format!("Host '{}' is not listed in group '{}'", host, from)));
+        }
+        if inventory.group(to).map(|g| g.has_host(host)).unwrap_or(false) {
+            return Err(edit_error(&format!("Host '{}' is already in group '{}'", host, to)));
+        }
+
+        if is_yaml_file(&self.path) {
+            self.yaml_move_host(host, from, to)
+        } else {
+            self.ini_move_host(host, from, to)
+        }
+    }
+
+    fn ini_add_host(&mut self, host: &str, address: &str, groups: &[String]) -> Result<(), Error> {
+        let definition = format!("{} ansible_host={}", host, address);
+        let top_level_end = self.lines.iter().position(|l| l.trim_start().starts_with('['))
+            .unwrap_or(self.lines.len());
+        let last_top_level_host = (0..top_level_end).rev()
+            .find(|&i| ini_host_line(&self.lines[i]).is_some());
+
+        let mut member_lines: Vec<String> = groups.iter().map(|_| host.to_string()).collect();
+        match last_top_level_host {
+            Some(i) => self.lines.insert(i + 1, definition),
+            None => member_lines[0] = definition
+        }
+
+        for (group, line) in groups.iter().zip(member_lines) {
+            self.ini_add_member(group, line);
+        }
+
+        Ok(())
+    }
+
+    fn ini_add_member(&mut self, group: &str, line: String) {
+        match self.ini_section(group) {
+            Some((start, end)) => {
+                let last = (start..end).rev()
+                    .find(|&i| ini_host_line(&self.lines[i]).is_some())
+                    .unwrap_or(start);
+                self.lines.insert(last + 1, line);
+            },
+            None => {
+                if self.lines.last().map(|l| !l.trim().is_empty()).unwrap_or(false) {
+                    self.lines.push(String::new());
+                }
+                self.lines.push(format!("[{}]", group));
+                self.lines.push(line);
+            }
+        }
+    }
+
+    fn ini_remove_host(&mut self, host: &str) -> Result<(), Error> {
+        let mut to_remove = Vec::new();
+        let mut in_hosts_section = true;
+
+        for (i, line) in self.lines.iter().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') {
+                in_hosts_section = !trimmed.contains(':');
+                continue;
+            }
+            if !in_hosts_section {
+                continue;
+            }
+
+            match ini_host_line(line).map(|name| host_matches(&name, host)) {
+                Some(HostMatch::Exact) => to_remove.push(i),
+                Some(HostMatch::Range) => {
+                    return Err(edit_error(&format!("Host '{}' is defined through a range at {}:{}, please edit it manually",
+                        host, self.path.display(), i + 1)));
+                },
+                _ => {}
+            }
+        }
+
+        for i in to_remove.into_iter().rev() {
+            self.lines.remove(i);
+        }
+
+        Ok(())
+    }
+
+    fn ini_move_host(&mut self, host: &str, from: &str, to: &str) -> Result<(), Error> {
+        let (start, end) = self.ini_section(from)
+            .ok_or_else(|| edit_error(&format!("Group '{}' has no hosts section", from)))?;
+        let index = (start..end)
+            .find(|&i| ini_host_line(&self.lines[i]).map(|name| host_matches(&name, host)) == Some(HostMatch::Exact))
+            .ok_or_else(|| edit_error(&format!("Host '{}' is not listed explicitly in group '{}', please edit it manually", host, from)))?;
+
+        let line = self.lines.remove(index).trim().to_string();
+        self.ini_add_member(to, line);
+
+        Ok(())
+    }
+
+    /// Returns the range of lines after the `[group]` header and before the
+    /// next section.
+    fn ini_section(&self, group: &str) -> Option<(usize, usize)> {
+        let header = format!("[{}]", group);
+        let start = self.lines.iter().position(|l| l.trim() == header)?;
+        let end = (start + 1..self.lines.len())
+            .find(|&i| self.lines[i].trim_start().starts_with('['))
+            .unwrap_or(self.lines.len());
+
+        Some((start, end))
+    }
+
+    fn yaml_add_host(&mut self, host: &str, address: &str, groups: &[String]) -> Result<(), Error> {
+        let unit = self.yaml_indent_unit();
+        let definition = vec![
+            format!("{}:", host),
+            format!("{}ansible_host: {}", " ".repeat(unit), address)
+        ];
+
+        let root = self.yaml_root()?;
+        let defines_hosts_in_all = find_yaml_group(&root, ALL_GROUP)
+            .and_then(|(_, all)| all.get("hosts"))
+            .and_then(|(_, hosts)| hosts.entries())
+            .map(|entries| !entries.is_empty())
+            .unwrap_or(false);
+
+        if defines_hosts_in_all && !groups.iter().any(|g| g == ALL_GROUP) {
+            self.yaml_add_member(ALL_GROUP, definition)?;
+            for group in groups {
+                self.yaml_add_member(group, vec![format!("{}:", host)])?;
+            }
+        } else {
+            self.yaml_add_member(&groups[0], definition)?;
+            for group in &groups[1..] {
+                self.yaml_add_member(group, vec![format!("{}:", host)])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds a host entry, given as lines relative to the host's indentation,
+    /// to the hosts of a group, creating the group if it doesn't exist.
+    fn yaml_add_member(&mut self, group: &str, host_lines: Vec<String>) -> Result<(), Error> {
+        let unit = self.yaml_indent_unit();
+        let root = self.yaml_root()?;
+
+        if let Some((key, value)) = find_yaml_group(&root, group) {
+            let key_index = key.line - 1;
+            let group_indent = indent_of(&self.lines[key_index]);
+
+            match value.get("hosts") {
+                Some((hosts_key, hosts)) => {
+                    let hosts_index = hosts_key.line - 1;
+                    self.check_block_style(hosts_index)?;
+                    let indent = match hosts.entries().and_then(|e| e.first()) {
+                        Some((first, _)) => indent_of(&self.lines[first.line - 1]),
+                        None => indent_of(&self.lines[hosts_index]) + unit
+                    };
+                    let end = block_end(&self.lines, hosts_index);
+                    self.insert_lines(end + 1, &host_lines, indent);
+                },
+                None => {
+                    self.check_block_style(key_index)?;
+                    let mut lines = vec!["hosts:".to_string()];
+                    lines.extend(host_lines.iter().map(|l| format!("{}{}", " ".repeat(unit), l)));
+                    self.insert_lines(key_index + 1, &lines, group_indent + unit);
+                }
+            }
+
+            return Ok(());
+        }
+
+        let mut group_lines = vec![format!("{}:", group), format!("{}hosts:", " ".repeat(unit))];
+        group_lines.extend(host_lines.iter().map(|l| format!("{}{}", " ".repeat(2 * unit), l)));
+
+        match find_yaml_group(&root, ALL_GROUP) {
+            Some((all_key, all)) => {
+                let all_index = all_key.line - 1;
+                match all.get("children") {
+                    Some((children_key, children)) => {
+                        let children_index = children_key.line - 1;
+                        self.check_block_style(children_index)?;
+                        let indent = match children.entries().and_then(|e| e.first()) {
+                            Some((first, _)) => indent_of(&self.lines[first.line - 1]),
+                            None => indent_of(&self.lines[children_index]) + unit
+                        };
+                        let end = block_end(&self.lines, children_index);
+                        self.insert_lines(end + 1, &group_lines, indent);
+                    },
+                    None => {
+                        self.check_block_style(all_index)?;
+                        let mut lines = vec!["children:".to_string()];
+                        lines.extend(group_lines.iter().map(|l| format!("{}{}", " ".repeat(unit), l)));
+                        let end = block_end(&self.lines, all_index);
+                        self.insert_lines(end + 1, &lines, indent_of(&self.lines[all_index]) + unit);
+                    }
+                }
+            },
+            None => {
+                let end = self.lines.len();
+                self.insert_lines(end, &group_lines, 0);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn yaml_remove_host(&mut self, host: &str) -> Result<(), Error> {
+        let root = self.yaml_root()?;
+        let mut ranges = Vec::new();
+
+        for (key, _) in yaml_host_entries(&root) {
+            match key.as_str().map(|name| host_matches(name, host)) {
+                Some(HostMatch::Exact) => ranges.push((key.line - 1, block_end(&self.lines, key.line - 1))),
+                Some(HostMatch::Range) => {
+                    return Err(edit_error(&format!("Host '{}' is defined through a range at {}:{}, please edit it manually",
+                        host, self.path.display(), key.line)));
+                },
+                _ => {}
+            }
+        }
+
+        ranges.sort_unstable();
+        for (start, end) in ranges.into_iter().rev() {
+            self.lines.drain(start..=end);
+        }
+
+        Ok(())
+    }
+
+    fn yaml_move_host(&mut self, host: &str, from: &str, to: &str) -> Result<(), Error> {
+        let root = self.yaml_root()?;
+        let key = find_yaml_group(&root, from)
+            .and_then(|(_, group)| group.get("hosts"))
+            .and_then(|(_, hosts)| hosts.entries())
+            .and_then(|entries| entries.iter().find(|(k, _)| k.as_str() == Some(host)))
+            .map(|(k, _)| k.clone())
+            .ok_or_else(|| edit_error(&format!("Host '{}' is not listed explicitly in group '{}', please edit it manually", host, from)))?;
+
+        let start = key.line - 1;
+        let end = block_end(&self.lines, start);
+        let indent = indent_of(&self.lines[start]);
+        let host_lines: Vec<String> = self.lines.drain(start..=end)
+            .map(|l| {
+                let strip = indent.min(indent_of(&l));
+                l[strip..].to_string()
+            })
+            .collect();
+
+        self.yaml_add_member(to, host_lines)
+    }
+
+    fn yaml_root(&self) -> Result<Node, Error> {
+        match yaml::load(&self.contents(), &self.path)? {
+            Some(node) if node.entries().is_some() => Ok(node),
+            Some(node) if !node.is_null() => Err(edit_error("Inventory must be a mapping of groups")),
+            _ => Ok(Node { kind: yaml::NodeKind::Mapping(Vec::new()), line: 1 })
+        }
+    }
+
+    /// Guesses the indentation used in the file from its first indented line.
+    fn yaml_indent_unit(&self) -> usize {
+        self.lines.iter()
+            .filter(|l| !is_blank_or_comment(l))
+            .map(|l| indent_of(l))
+            .find(|&i| i > 0)
+            .unwrap_or(DEFAULT_YAML_INDENT)
+    }
+
+    /// Makes sure the value of the key in `index` is not written in flow style
+    /// (`{...}` or `[...]`), which can't be edited line by line.
+    fn check_block_style(&self, index: usize) -> Result<(), Error> {
+        let line = &self.lines[index];
+        let value = line.split_once(':').map(|(_, v)| v.trim()).unwrap_or("");
+        if value.starts_with('{') || value.starts_with('[') {
+            return Err(edit_error(&format!("{}:{}: Inline mappings can't be edited, please edit them manually",
+                self.path.display(), index + 1)));
+        }
+
+        Ok(())
+    }
+
+    fn insert_lines(&mut self, at: usize, lines: &[String], indent: usize) {
+        for (offset, line) in lines.iter().enumerate() {
+            let text = if line.is_empty() { String::new() } else { format!("{}{}", " ".repeat(indent), line) };
+            self.lines.insert(at + offset, text);
+        }
+    }
+}
+
+/// Returns the host name or range of an INI host line.
+fn ini_host_line(line: &str) -> Option<String> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') || trimmed.starts_with('[') {
+        return None;
+    }
+
+    split_line(trimmed).ok()
+        .and_then(|tokens| tokens.into_iter().next())
+        .map(|token| split_host_port(&token).0.to_string())
+}
+
+fn host_matches(name: &str, host: &str) -> HostMatch {
+    if name == host {
+        HostMatch::Exact
+    } else if name.contains('[') && expand_host_range(name).map(|hosts| hosts.iter().any(|h| h == host)).unwrap_or(false) {
+        HostMatch::Range
+    } else {
+        HostMatch::None
+    }
+}
+
+/// Finds a group anywhere in a YAML inventory, returning its key and value.
+fn find_yaml_group<'a>(root: &'a Node, group: &str) -> Option<(&'a Node, &'a Node)> {
+    for (key, value) in root.entries()? {
+        if key.as_str() == Some(group) {
+            return Some((key, value));
+        }
+        if let Some(found) = value.get("children").and_then(|(_, children)| find_yaml_group(children, group)) {
+            return Some(found);
+        }
+    }
+
+    None
+}
+
+/// Returns the host entries of all groups in a YAML inventory.
+fn yaml_host_entries(root: &Node) -> Vec<(&Node, &Node)> {
+    let mut entries = Vec::new();
+
+    if let Some(groups) = root.entries() {
+        for (_, group) in groups {
+            if let Some(hosts) = group.get("hosts").and_then(|(_, h)| h.entries()) {
+                entries.extend(hosts.iter().map(|(k, v)| (k, v)));
+            }
+            if let Some((_, children)) = group.get("children") {
+                entries.extend(yaml_host_entries(children));
+            }
+        }
+    }
+
+    entries
+}
+
+/// Returns the index of the last line of the YAML block starting at `start`,
+/// that is, the last non-blank line indented deeper than it.
+fn block_end(lines: &[String], start: usize) -> usize {
+    let indent = indent_of(&lines[start]);
+    let mut end = start;
+
+    for (i, line) in lines.iter().enumerate().skip(start + 1) {
+        if is_blank_or_comment(line) {
+            continue;
+        }
+        if indent_of(line) <= indent {
+            break;
+        }
+        end = i;
+    }
+
+    end
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+fn is_blank_or_comment(line: &str) -> bool {
+    let trimmed = line.trim();
+    trimmed.is_empty() || trimmed.starts_with('#')
+}
+
+fn edit_error(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use rstest::rstest;
+    use super::InventoryEditor;
+
+    const INI_INVENTORY: &str = "\
+# Machines
+master1 ansible_host=192.168.0.10
+worker1 ansible_host=192.168.0.11
+
+[cluster]
+master1
+worker1
+
+[cluster_managers]
+master1
+
+[cluster_workers]
+# Raspberry Pis
+worker1
+";
+
+    const YAML_INVENTORY: &str = "\
+all:
+  children:
+    cluster:
+      children:
+        cluster_managers:
+          hosts:
+            master1:
+              ansible_host: 192.168.0.10
+        cluster_workers:
+          # Raspberry Pis
+          hosts:
+            worker1:
+              ansible_host: 192.168.0.11
+";
+
+    fn groups(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[rstest]
+    fn hosts_are_added_to_ini_inventories() {
+        let mut editor = InventoryEditor::from_contents(Path::new("hosts"), INI_INVENTORY);
+        editor.add_host("worker2", "192.168.0.12", &groups(&["cluster", "cluster_workers"])).unwrap();
+
+        assert_eq!(editor.contents(), INI_INVENTORY
+            .replace("worker1 ansible_host=192.168.0.11\n", "worker1 ansible_host=192.168.0.11\nworker2 ansible_host=192.168.0.12\n")
+            .replace("worker1\n\n", "worker1\nworker2\n\n")
+            .replace("# Raspberry Pis\nworker1\n", "# Raspberry Pis\nworker1\nworker2\n"));
+    }
+
+    #[rstest]
+    fn hosts_are_added_to_new_ini_groups() {
+        let mut editor = InventoryEditor::from_contents(Path::new("hosts"), "[cluster]\nmaster1 ansible_host=192.168.0.10\n");
+        editor.add_host("worker1", "192.168.0.11", &groups(&["cluster", "cluster_workers"])).unwrap();
+
+        assert_eq!(editor.contents(),
+            "[cluster]\nmaster1 ansible_host=192.168.0.10\nworker1 ansible_host=192.168.0.11\n\n[cluster_workers]\nworker1\n");
+    }
+
+    #[rstest]
+    fn hosts_are_removed_from_ini_inventories() {
+        let mut editor = InventoryEditor::from_contents(Path::new("hosts"), INI_INVENTORY);
+        editor.remove_host("worker1").unwrap();
+
+        assert_eq!(editor.contents(), INI_INVENTORY
+            .replace("worker1 ansible_host=192.168.0.11\n", "")
+            .replace("worker1\n", ""));
+    }
+
+    #[rstest]
+    fn hosts_are_moved_in_ini_inventories() {
+        let mut editor = InventoryEditor::from_contents(Path::new("hosts"), INI_INVENTORY);
+        editor.move_host("worker1", "cluster_workers", "cluster_managers").unwrap();
+
+        assert_eq!(editor.contents(), INI_INVENTORY
+            .replace("[cluster_managers]\nmaster1\n", "[cluster_managers]\nmaster1\nworker1\n")
+            .replace("# Raspberry Pis\nworker1\n", "# Raspberry Pis\n"));
+    }
+
+    #[rstest]
+    fn hosts_are_added_to_yaml_inventories() {
+        let mut editor = InventoryEditor::from_contents(Path::new("hosts.yaml"), YAML_INVENTORY);
+        editor.add_host("worker2", "192.168.0.12", &groups(&["cluster_workers"])).unwrap();
+
+        assert_eq!(editor.contents(), format!("{}            worker2:\n              ansible_host: 192.168.0.12\n", YAML_INVENTORY));
+        let inventory = editor.inventory().unwrap();
+        assert_eq!(inventory.host_var("worker2", "ansible_host").unwrap(), "192.168.0.12");
+        assert!(inventory.group("cluster_workers").unwrap().has_host("worker2"));
+    }
+
+    #[rstest]
+    fn hosts_are_added_to_new_yaml_groups() {
+        let mut editor = InventoryEditor::from_contents(Path::new("hosts.yaml"), YAML_INVENTORY);
+        editor.add_host("nas", "192.168.0.20", &groups(&["storage"])).unwrap();
+
+        assert_eq!(editor.contents(), format!("{}    storage:\n      hosts:\n        nas:\n          ansible_host: 192.168.0.20\n", YAML_INVENTORY));
+    }
+
+    #[rstest]
+    fn hosts_are_removed_from_yaml_inventories() {
+        let mut editor = InventoryEditor::from_contents(Path::new("hosts.yaml"), YAML_INVENTORY);
+        editor.remove_host("worker1").unwrap();
+
+        assert_eq!(editor.contents(), YAML_INVENTORY.replace("            worker1:\n              ansible_host: 192.168.0.11\n", ""));
+        assert!(editor.inventory().unwrap().host("worker1").is_none());
+    }
+
+    #[rstest]
+    fn hosts_are_moved_in_yaml_inventories() {
+        let mut editor = InventoryEditor::from_contents(Path::new("hosts.yaml"), YAML_INVENTORY);
+        editor.move_host("worker1", "cluster_workers", "cluster_managers").unwrap();
+
+        assert_eq!(editor.contents(), YAML_INVENTORY
+            .replace("            worker1:\n              ansible_host: 192.168.0.11\n", "")
+            .replace("              ansible_host: 192.168.0.10\n",
+                "              ansible_host: 192.168.0.10\n            worker1:\n              ansible_host: 192.168.0.11\n"));
+    }
+
+    #[rstest]
+    #[case("hosts", "worker[1:3] ansible_user=pi\n[cluster]\nworker[1:3]\n")]
+    #[case("hosts.yaml", "cluster:\n  hosts:\n    worker[1:3]:\n")]
+    fn hosts_in_ranges_are_not_removed(
+        #[case] file_name: &str,
+        #[case] contents: &str) {
+        let mut editor = InventoryEditor::from_contents(Path::new(file_name), contents);
+        assert!(editor.remove_host("worker2").is_err());
+        assert_eq!(editor.contents(), contents);
+    }
+
+    #[rstest]
+    #[case("hosts", "master1")]
+    #[case("hosts.yaml", "master1")]
+    fn existing_hosts_are_not_added(
+        #[case] file_name: &str,
+        #[case] host: &str) {
+        let contents = if file_name.ends_with(".yaml") { YAML_INVENTORY } else { INI_INVENTORY };
+        let mut editor = InventoryEditor::from_contents(Path::new(file_name), contents);
+        assert!(editor.add_host(host, "192.168.0.99", &groups(&["cluster"])).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::io::Error;
+use std::path::Path;
+
+use serde_yaml::Value;
+
+use crate::utils::inventory::{expand_host_range, parse_error, Inventory, Location};
+
+/// Kind of entries expected in the current INI section.
+enum Section {
+    Hosts(Option<String>),
+    Vars(String),
+    Children(String)
+}
+
+/// Parses an inventory in Ansible's INI format, adding its hosts and groups
+/// to `inventory`.
+pub fn parse(inventory: &mut Inventory, contents: &str, file: &Path) -> Result<(), Error> {
+    let mut section = Section::Hosts(None);
+
+    for (index, raw_line) in contents.lines().enumerate() {
+        let location = Location::new(file, index + 1);
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            section = parse_section_header(inventory, line, &location)?;
+            continue;
+        }
+
+        match &section {
+            Section::Hosts(group) => {
+                let tokens = split_line(line)
+                    .map_err(|e| parse_error(&location, &e))?;
+                if tokens.is_empty() {
+                    continue;
+                }
+
+                let (pattern, port) = split_host_port(&tokens[0]);
+                let hosts = expand_host_range(pattern)
+                    .map_err(|e| parse_error(&location, &e))?;
+                let mut vars: Vec<(String, Value)> = Vec::new();
+                for token in &tokens[1..] {
+                    match token.split_once('=') {
+                        Some((key, value)) if !key.is_empty() => vars.push((key.to_string(), Value::String(value.to_string()))),
+                        _ => return Err(parse_error(&location, &format!("Expected key=value host variable, found '{}'", token)))
+                    }
+                }
+                if let Some(p) = port {
+                    vars.push(("ansible_port".to_string(), Value::String(p.to_string())));
+                }
+
+                for host in hosts {
+                    let h = inventory.ensure_host(&host, &location);
+                    for (key, value) in &vars {
+                        h.vars.insert(key.clone(), value.clone());
+                    }
+                    if let Some(g) = group {
+                        inventory.add_group_host(g, &host, &location);
+                    }
+                }
+            },
+
+            Section::Vars(group) => {
+                match line.split_once('=') {
+                    Some((key, value)) if !key.trim().is_empty() => {
+                        let value = unquote(value.trim());
+                        inventory.set_group_var(group, key.trim(), Value::String(value));
+                    },
+                    _ => return Err(parse_error(&location, &format!("Expected key=value group variable, found '{}'", line)))
+                }
+            },
+
+            Section::Children(group) => {
+                let child = strip_comment(line);
+                if child.contains(char::is_whitespace) {
+                    return Err(parse_error(&location, &format!("Invalid child group name '{}'", child)));
+                }
+                inventory.add_group_child(group, child, &location);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_section_header(inventory: &mut Inventory, line: &str, location: &Location) -> Result<Section, Error> {
+    let header = strip_comment(line);
+    if !header.ends_with(']') {
+        return Err(parse_error(location, &format!("Invalid section header '{}'", line)));
+    }
+
+    let name = &header[1..header.len() - 1];
+    let (group, kind) = match name.split_once(':') {
+        Some((g, k)) => (g, Some(k)),
+        None => (name, None)
+    };
+    if group.is_empty() || group.contains(char::is_whitespace) {
+        return Err(parse_error(location, &format!("Invalid group name '{}'", group)));
+    }
+
+    inventory.ensure_group(group, location);
+    match kind {
+        None => Ok(Section::Hosts(Some(group.to_string()))),
+        Some("vars") => Ok(Section::Vars(group.to_string())),
+        Some("children") => Ok(Section::Children(group.to_string())),
+        Some(other) => Err(parse_error(location, &format!("Unknown section type '{}'", other)))
+    }
+}
+
+/// Splits a host line into shell-like tokens, honouring quotes and stopping
+/// at the first comment.
+pub(super) fn split_line(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_token = false;
+    let mut quote: Option<char> = None;
+
+    for c in line.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => current.push(c),
+            None => {
+                if c == '"' || c == '\'' {
+                    quote = Some(c);
+                    in_token = true;
+                } else if c.is_whitespace() {
+                    if in_token {
+                        tokens.push(current.clone());
+                        current.clear();
+                        in_token = false;
+                    }
+                } else if c == '#' && !in_token {
+                    break;
+                } else {
+                    current.push(c);
+                    in_token = true;
+                }
+            }
+        }
+    }
+
+    if quote.is_some() {
+        return Err(format!("Unterminated quote in '{}'", line));
+    }
+    if in_token {
+        tokens.push(current);
+    }
+
+    Ok(tokens)
+}
+
+/// Splits the `host:port` shorthand, ignoring colons inside ranges and
+/// IPv6 addresses.
+pub(super) fn split_host_port(host: &str) -> (&str, Option<&str>) {
+    let mut depth = 0;
+    let mut colons = Vec::new();
+    for (i, c) in host.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ':' if depth == 0 => colons.push(i),
+            _ => {}
+        }
+    }
+
+    if colons.len() == 1 {
+        let port = &host[colons[0] + 1..];
+        if !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()) {
+            return (&host[..colons[0]], Some(port));
+        }
+    }
+
+    (host, None)
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find(" #").or_else(|| line.find("\t#")) {
+        Some(i) => line[..i].trim(),
+        None => line.trim()
+    }
+}
+
+fn unquote(value: &str) -> String {
+    let value = strip_comment(value);
+    if value.len() >= 2
+        && ((value.starts_with('"') && value.ends_with('"')) || (value.starts_with('\'') && value.ends_with('\''))) {
+        value[1..value.len() - 1].to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use rstest::rstest;
+    use crate::utils::inventory::Inventory;
+    use super::{split_host_port, split_line};
+
+    #[rstest]
+    #[case("master1 ansible_host=10.0.0.1", vec!["master1", "ansible_host=10.0.0.1"])]
+    #[case("master1 msg=\"hello world\" # comment", vec!["master1", "msg=hello world"])]
+    #[case("master1 # ansible_host=10.0.0.1", vec!["master1"])]
+    fn host_lines_are_correctly_split(
+        #[case] line: &str,
+        #[case] expected_tokens: Vec<&str>) {
+        assert_eq!(split_line(line).unwrap(), expected_tokens);
+    }
+
+    #[rstest]
+    #[case("master1:2222", "master1", Some("2222"))]
+    #[case("worker[1:3]", "worker[1:3]", None)]
+    #[case("fe80::1", "fe80::1", None)]
+    fn host_ports_are_correctly_split(
+        #[case] host: &str,
+        #[case] expected_host: &str,
+        #[case] expected_port: Option<&str>) {
+        assert_eq!(split_host_port(host), (expected_host, expected_port));
+    }
+
+    #[rstest]
+    #[case("[cluster")]
+    #[case("[cluster:hosts]")]
+    #[case("master1 ansible_host")]
+    #[case("[cluster:vars]\nansible_user")]
+    fn invalid_inventories_are_rejected(#[case] contents: &str) {
+        assert!(Inventory::parse_ini(contents, Path::new("hosts")).is_err());
+    }
+
+    #[rstest]
+    fn children_and_ungrouped_hosts_are_parsed() {
+        let contents = "lonely\n[cluster:children]\nmanagers\n[managers]\nmaster1:2222\n";
+        let inventory = Inventory::parse_ini(contents, Path::new("hosts")).unwrap();
+
+        assert!(inventory.group("cluster").unwrap().has_child("managers"));
+        assert!(inventory.group("ungrouped").unwrap().has_host("lonely"));
+        assert_eq!(inventory.host_var("master1", "ansible_port").unwrap(), "2222");
+        assert_eq!(inventory.group_hosts("cluster").len(), 1);
+    }
+}
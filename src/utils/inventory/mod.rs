@@ -0,0 +1,689 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde_yaml::Value;
+
+use crate::utils::settings::ClusterSettings;
+
+pub mod edit;
+mod pattern;
+mod ini;
+pub mod validate;
+mod yaml;
+
+/// Name of the implicit group containing every host in the inventory.
+pub const ALL_GROUP: &str = "all";
+/// Name of the implicit group containing hosts not listed in any other group.
+pub const UNGROUPED_GROUP: &str = "ungrouped";
+
+/// Inventory file used by Ansible when none is specified.
+const DEFAULT_INVENTORY_FILE: &str = "/etc/ansible/hosts";
+
+/// File extensions Ansible ignores when reading an inventory directory.
+const IGNORED_EXTENSIONS: [&str; 8] = ["~", ".orig", ".ini", ".cfg", ".retry", ".bak", ".pyc", ".swp"];
+
+/// Variables attached to a host or group.
+pub type Variables = BTreeMap<String, Value>;
+
+/// Position in an inventory file where an item was defined.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub file: PathBuf,
+    pub line: usize
+}
+
+impl Location {
+    pub fn new(file: &Path, line: usize) -> Self {
+        Location {
+            file: file.to_path_buf(),
+            line
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "{}:{}", self.file.display(), self.line)
+        } else {
+            write!(f, "{}", self.file.display())
+        }
+    }
+}
+
+/// A single machine in the inventory.
+#[derive(Clone, Debug)]
+pub struct Host {
+    pub name: String,
+    pub vars: Variables,
+    pub location: Location
+}
+
+/// A reference from a group to a host or child group, with where it was listed.
+#[derive(Clone, Debug)]
+pub struct Membership {
+    pub name: String,
+    pub location: Location
+}
+
+/// A group of hosts in the inventory.
+#[derive(Clone, Debug)]
+pub struct Group {
+    pub name: String,
+    pub hosts: Vec<Membership>,
+    pub children: Vec<Membership>,
+    pub vars: Variables,
+    pub location: Location
+}
+
+impl Group {
+    fn new(name: &str, location: Location) -> Self {
+        Group {
+            name: name.to_string(),
+            hosts: Vec::new(),
+            children: Vec::new(),
+            vars: Variables::new(),
+            location
+        }
+    }
+
+    pub fn has_host(&self, host: &str) -> bool {
+        self.hosts.iter().any(|m| m.name == host)
+    }
+
+    pub fn has_child(&self, group: &str) -> bool {
+        self.children.iter().any(|m| m.name == group)
+    }
+}
+
+/// In-memory representation of an Ansible inventory, in INI or YAML format.
+#[derive(Clone, Debug, Default)]
+pub struct Inventory {
+    files: Vec<PathBuf>,
+    hosts: Vec<Host>,
+    groups: Vec<Group>
+}
+
+impl Inventory {
+    /// Loads the inventory configured in the settings, or Ansible's default one.
+    pub fn from_settings(settings: &ClusterSettings) -> Result<Inventory, Error> {
+        Inventory::load(&inventory_path(settings))
+    }
+
+    /// Loads an inventory file, or all inventory files in a directory, together
+    /// with the `group_vars` and `host_vars` directories next to them.
+    pub fn load(path: &Path) -> Result<Inventory, Error> {
+        let mut inventory = Inventory::default();
+
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|p| p.is_file() && !is_ignored_inventory_file(p))
+                .collect();
+            entries.sort();
+
+            for entry in entries {
+                inventory.read_file(&entry)?;
+            }
+            inventory.finish();
+            inventory.load_vars_directories(path)?;
+        } else {
+            inventory.read_file(path)?;
+            inventory.finish();
+            if let Some(parent) = path.parent() {
+                inventory.load_vars_directories(parent)?;
+            }
+        }
+
+        Ok(inventory)
+    }
+
+    /// Parses the contents of an inventory file, as if they were read from
+    /// `file`, together with the `group_vars` and `host_vars` directories
+    /// next to it.
+    pub fn parse(contents: &str, file: &Path) -> Result<Inventory, Error> {
+        let mut inventory = Inventory::default();
+        inventory.read_contents(contents, file)?;
+        inventory.finish();
+        if let Some(parent) = file.parent() {
+            inventory.load_vars_directories(parent)?;
+        }
+
+        Ok(inventory)
+    }
+
+    /// Parses an inventory in INI format.
+    #[cfg(test)]
+    pub fn parse_ini(contents: &str, file: &Path) -> Result<Inventory, Error> {
+        let mut inventory = Inventory::default();
+        inventory.files.push(file.to_path_buf());
+        ini::parse(&mut inventory, contents, file)?;
+        inventory.finish();
+
+        Ok(inventory)
+    }
+
+    /// Parses an inventory in YAML format.
+    #[cfg(test)]
+    pub fn parse_yaml(contents: &str, file: &Path) -> Result<Inventory, Error> {
+        let mut inventory = Inventory::default();
+        inventory.files.push(file.to_path_buf());
+        yaml::parse(&mut inventory, contents, file)?;
+        inventory.finish();
+
+        Ok(inventory)
+    }
+
+    /// Returns the inventory files the hosts and groups were read from.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    pub fn hosts(&self) -> &[Host] {
+        &self.hosts
+    }
+
+    pub fn host(&self, name: &str) -> Option<&Host> {
+        self.hosts.iter().find(|h| h.name == name)
+    }
+
+    pub fn groups(&self) -> &[Group] {
+        &self.groups
+    }
+
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.groups.iter().find(|g| g.name == name)
+    }
+
+    /// Returns all hosts in a group, including those in its child groups, in
+    /// inventory order.
+    pub fn group_hosts(&self, group: &str) -> Vec<&Host> {
+        if group == ALL_GROUP {
+            return self.hosts.iter().collect();
+        }
+
+        let mut names = HashSet::new();
+        let mut visited = HashSet::new();
+        self.collect_group_hosts(group, &mut names, &mut visited);
+
+        self.hosts.iter()
+            .filter(|h| names.contains(h.name.as_str()))
+            .collect()
+    }
+
+    /// Returns all groups a host belongs to, directly or through
+    /// child groups, sorted from the least to the most specific.
+    pub fn host_groups(&self, host: &str) -> Vec<&Group> {
+        let mut groups: Vec<&Group> = self.groups.iter()
+            .filter(|g| g.name == ALL_GROUP || self.group_hosts(&g.name).iter().any(|h| h.name == host))
+            .collect();
+        groups.sort_by_key(|g| self.group_depth(&g.name));
+
+        groups
+    }
+
+    /// Returns the effective variables for a host, merging the variables of
+    /// all its groups (from `all` to the most specific) with its own.
+    pub fn host_vars(&self, host: &str) -> Variables {
+        let mut vars = Variables::new();
+
+        for group in self.host_groups(host) {
+            for (key, value) in &group.vars {
+                vars.insert(key.clone(), value.clone());
+            }
+        }
+
+        if let Some(h) = self.host(host) {
+            for (key, value) in &h.vars {
+                vars.insert(key.clone(), value.clone());
+            }
+        }
+
+        vars
+    }
+
+    /// Returns the effective value of a host variable as a string.
+    pub fn host_var(&self, host: &str, name: &str) -> Option<String> {
+        self.host_vars(host).get(name).and_then(value_to_string)
+    }
+
+    fn collect_group_hosts<'a>(&'a self, group: &'a str, names: &mut HashSet<&'a str>, visited: &mut HashSet<&'a str>) {
+        if !visited.insert(group) {
+            return;
+        }
+
+        if let Some(g) = self.group(group) {
+            for member in &g.hosts {
+                names.insert(member.name.as_str());
+            }
+            for child in &g.children {
+                self.collect_group_hosts(child.name.as_str(), names, visited);
+            }
+        }
+    }
+
+    fn group_depth(&self, group: &str) -> usize {
+        fn depth(inventory: &Inventory, group: &str, visited: &mut HashSet<String>) -> usize {
+            if group == ALL_GROUP || !visited.insert(group.to_string()) {
+                return 0;
+            }
+
+            inventory.groups.iter()
+                .filter(|g| g.has_child(group))
+                .map(|g| depth(inventory, &g.name, visited) + 1)
+                .max()
+                .unwrap_or(1)
+        }
+
+        depth(self, group, &mut HashSet::new())
+    }
+
+    /// Returns whether `child` is a child group of `parent`, directly or
+    /// through other child groups.
+    pub fn is_child_group(&self, parent: &str, child: &str) -> bool {
+        fn find(inventory: &Inventory, parent: &str, child: &str, visited: &mut HashSet<String>) -> bool {
+            if !visited.insert(parent.to_string()) {
+                return false;
+            }
+
+            match inventory.group(parent) {
+                Some(g) => g.children.iter().any(|c| c.name == child || find(inventory, &c.name, child, visited)),
+                None => false
+            }
+        }
+
+        find(self, parent, child, &mut HashSet::new())
+    }
+
+    fn read_file(&mut self, path: &Path) -> Result<(), Error> {
+        info!("Reading inventory file {}", path.display());
+        let contents = fs::read_to_string(path)?;
+        self.read_contents(&contents, path)
+    }
+
+    fn read_contents(&mut self, contents: &str, path: &Path) -> Result<(), Error> {
+        self.files.push(path.to_path_buf());
+
+        if is_yaml_file(path) {
+            yaml::parse(self, contents, path)
+        } else {
+            ini::parse(self, contents, path)
+        }
+    }
+
+    fn load_vars_directories(&mut self, base: &Path) -> Result<(), Error> {
+        let group_names: Vec<String> = self.groups.iter().map(|g| g.name.clone()).collect();
+        for name in group_names {
+            for (key, value) in read_vars_files(&base.join("group_vars"), &name)? {
+                self.set_group_var(&name, &key, value);
+            }
+        }
+
+        let host_names: Vec<String> = self.hosts.iter().map(|h| h.name.clone()).collect();
+        for name in host_names {
+            for (key, value) in read_vars_files(&base.join("host_vars"), &name)? {
+                self.set_host_var(&name, &key, value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Makes sure the implicit groups exist and that every host belongs to
+    /// some group other than `all`.
+    fn finish(&mut self) {
+        let no_location = Location::new(Path::new(""), 0);
+        self.ensure_group(ALL_GROUP, &no_location);
+        self.ensure_group(UNGROUPED_GROUP, &no_location);
+
+        let grouped: HashSet<String> = self.groups.iter()
+            .filter(|g| g.name != ALL_GROUP && g.name != UNGROUPED_GROUP)
+            .flat_map(|g| g.hosts.iter().map(|m| m.name.clone()))
+            .collect();
+        let ungrouped: Vec<(String, Location)> = self.hosts.iter()
+            .filter(|h| !grouped.contains(&h.name))
+            .map(|h| (h.name.clone(), h.location.clone()))
+            .collect();
+        for (name, location) in ungrouped {
+            self.add_group_host(UNGROUPED_GROUP, &name, &location);
+        }
+
+        let top_level: Vec<(String, Location)> = self.groups.iter()
+            .filter(|g| g.name != ALL_GROUP)
+            .filter(|g| !self.groups.iter().any(|parent| parent.name != ALL_GROUP && parent.has_child(&g.name)))
+            .map(|g| (g.name.clone(), g.location.clone()))
+            .collect();
+        for (name, location) in top_level {
+            self.add_group_child(ALL_GROUP, &name, &location);
+        }
+    }
+
+    fn ensure_host(&mut self, name: &str, location: &Location) -> &mut Host {
+        let index = match self.hosts.iter().position(|h| h.name == name) {
+            Some(i) => i,
+            None => {
+                self.hosts.push(Host {
+                    name: name.to_string(),
+                    vars: Variables::new(),
+                    location: location.clone()
+                });
+                self.hosts.len() - 1
+            }
+        };
+
+        &mut self.hosts[index]
+    }
+
+    fn ensure_group(&mut self, name: &str, location: &Location) -> &mut Group {
+        let index = match self.groups.iter().position(|g| g.name == name) {
+            Some(i) => i,
+            None => {
+                self.groups.push(Group::new(name, location.clone()));
+                self.groups.len() - 1
+            }
+        };
+
+        &mut self.groups[index]
+    }
+
+    fn set_host_var(&mut self, host: &str, key: &str, value: Value) {
+        if let Some(h) = self.hosts.iter_mut().find(|h| h.name == host) {
+            h.vars.insert(key.to_string(), value);
+        }
+    }
+
+    fn set_group_var(&mut self, group: &str, key: &str, value: Value) {
+        if let Some(g) = self.groups.iter_mut().find(|g| g.name == group) {
+            g.vars.insert(key.to_string(), value);
+        }
+    }
+
+    fn add_group_host(&mut self, group: &str, host: &str, location: &Location) {
+        let g = self.ensure_group(group, location);
+        if !g.has_host(host) {
+            g.hosts.push(Membership {
+                name: host.to_string(),
+                location: location.clone()
+            });
+        }
+    }
+
+    fn add_group_child(&mut self, group: &str, child: &str, location: &Location) {
+        self.ensure_group(child, location);
+        let g = self.ensure_group(group, location);
+        if !g.has_child(child) {
+            g.children.push(Membership {
+                name: child.to_string(),
+                location: location.clone()
+            });
+        }
+    }
+}
+
+/// Returns the path of the inventory configured in the settings, or Ansible's
+/// default one.
+pub fn inventory_path(settings: &ClusterSettings) -> PathBuf {
+    match &settings.inventory {
+        Some(v) => PathBuf::from(v),
+        None => match env::var("ANSIBLE_INVENTORY") {
+            Ok(v) if !v.is_empty() => PathBuf::from(v),
+            _ => PathBuf::from(DEFAULT_INVENTORY_FILE)
+        }
+    }
+}
+
+/// Returns the string representation of a scalar variable value.
+pub fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None
+    }
+}
+
+/// Expands host ranges like `worker[1:9]`, `node[01:10:2]` or `db-[a:c]` into
+/// the list of host names they represent.
+pub fn expand_host_range(pattern: &str) -> Result<Vec<String>, String> {
+    let start = match pattern.find('[') {
+        Some(i) => i,
+        None => return Ok(vec![pattern.to_string()])
+    };
+    let end = match pattern[start..].find(']') {
+        Some(i) => start + i,
+        None => return Err(format!("Unterminated range in host pattern '{}'", pattern))
+    };
+
+    let head = &pattern[..start];
+    let tail = &pattern[end + 1..];
+    let range: Vec<&str> = pattern[start + 1..end].split(':').collect();
+    if range.len() < 2 || range.len() > 3 {
+        return Err(format!("Invalid range in host pattern '{}'", pattern));
+    }
+
+    let (first, last) = (range[0], range[1]);
+    let step: usize = match range.get(2) {
+        Some(s) => s.parse().map_err(|_| format!("Invalid range step in host pattern '{}'", pattern))?,
+        None => 1
+    };
+    if step == 0 {
+        return Err(format!("Invalid range step in host pattern '{}'", pattern));
+    }
+
+    let items: Vec<String> = if let (Ok(a), Ok(b)) = (first.parse::<usize>(), last.parse::<usize>()) {
+        if a > b {
+            return Err(format!("Range start is greater than range end in host pattern '{}'", pattern));
+        }
+        let width = if first.len() > 1 && first.starts_with('0') { first.len() } else { 0 };
+        (a..=b).step_by(step).map(|i| format!("{:0width$}", i, width = width)).collect()
+    } else if first.len() == 1 && last.len() == 1
+        && first.chars().all(|c| c.is_ascii_alphabetic())
+        && last.chars().all(|c| c.is_ascii_alphabetic()) {
+        let (a, b) = (first.as_bytes()[0], last.as_bytes()[0]);
+        if a > b {
+            return Err(format!("Range start is greater than range end in host pattern '{}'", pattern));
+        }
+        (a..=b).step_by(step).map(|c| (c as char).to_string()).collect()
+    } else {
+        return Err(format!("Invalid range in host pattern '{}'", pattern));
+    };
+
+    let mut hosts = Vec::new();
+    for item in items {
+        for rest in expand_host_range(tail)? {
+            hosts.push(format!("{}{}{}", head, item, rest));
+        }
+    }
+
+    Ok(hosts)
+}
+
+fn is_yaml_file(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some("yml") | Some("yaml") | Some("json"))
+}
+
+fn is_ignored_inventory_file(path: &Path) -> bool {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    name.starts_with('.') || IGNORED_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+}
+
+/// Reads variables for a host or group from `<dir>/<name>`, which can be a
+/// YAML file, with or without extension, or a directory of YAML files.
+fn read_vars_files(dir: &Path, name: &str) -> Result<Variables, Error> {
+    let mut files: Vec<PathBuf> = Vec::new();
+
+    let base = dir.join(name);
+    if base.is_dir() {
+        let mut entries: Vec<PathBuf> = fs::read_dir(&base)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.is_file() && !is_ignored_inventory_file(p))
+            .collect();
+        entries.sort();
+        files.extend(entries);
+    } else {
+        for candidate in &[name.to_string(), format!("{}.yml", name), format!("{}.yaml", name), format!("{}.json", name)] {
+            let path = dir.join(candidate);
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+
+    let mut vars = Variables::new();
+    for file in files {
+        info!("Reading variables file {}", file.display());
+        let contents = fs::read_to_string(&file)?;
+        match serde_yaml::from_str::<Value>(&contents) {
+            Ok(Value::Mapping(mapping)) => {
+                for (key, value) in mapping {
+                    if let Some(k) = value_to_string(&key) {
+                        vars.insert(k, value);
+                    }
+                }
+            },
+            Ok(Value::Null) => {},
+            Ok(_) => warn!("Ignoring variables file {}, it does not contain a mapping", file.display()),
+            Err(e) => return Err(Error::new(ErrorKind::InvalidData, format!("{}: {}", file.display(), e)))
+        }
+    }
+
+    Ok(vars)
+}
+
+fn parse_error(location: &Location, msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("{}: {}", location, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use rstest::rstest;
+    use tempfile::TempDir;
+    use super::*;
+
+    const INI_INVENTORY: &str = "\
+# Machines
+master1 ansible_host=192.168.0.10
+worker[1:2] ansible_user=pi
+
+[cluster]
+master1
+worker1
+worker2
+
+[cluster_managers]
+master1
+
+[cluster_workers]
+worker[1:2]
+
+[cluster:vars]
+ansible_user=rodrigo
+";
+
+    const YAML_INVENTORY: &str = "\
+all:
+  hosts:
+    master1:
+      ansible_host: 192.168.0.10
+  children:
+    cluster:
+      children:
+        cluster_managers:
+          hosts:
+            master1:
+        cluster_workers:
+          hosts:
+            worker[1:2]:
+              ansible_user: pi
+      vars:
+        ansible_user: rodrigo
+";
+
+    #[rstest]
+    #[case("worker[1:3]", vec!["worker1", "worker2", "worker3"])]
+    #[case("node[08:10]", vec!["node08", "node09", "node10"])]
+    #[case("node[1:5:2]", vec!["node1", "node3", "node5"])]
+    #[case("db-[a:c].local", vec!["db-a.local", "db-b.local", "db-c.local"])]
+    #[case("r[1:2]n[1:2]", vec!["r1n1", "r1n2", "r2n1", "r2n2"])]
+    #[case("master1", vec!["master1"])]
+    fn host_ranges_are_correctly_expanded(
+        #[case] pattern: &str,
+        #[case] expected_hosts: Vec<&str>) {
+        assert_eq!(expand_host_range(pattern).unwrap(), expected_hosts);
+    }
+
+    #[rstest]
+    #[case("worker[1:")]
+    #[case("worker[3:1]")]
+    #[case("worker[1:2:0]")]
+    #[case("worker[a:10]")]
+    fn invalid_host_ranges_are_rejected(#[case] pattern: &str) {
+        assert!(expand_host_range(pattern).is_err());
+    }
+
+    #[rstest]
+    #[case(INI_INVENTORY, "hosts")]
+    #[case(YAML_INVENTORY, "hosts.yaml")]
+    fn inventory_is_correctly_parsed(
+        #[case] contents: &str,
+        #[case] file_name: &str) {
+        let path = Path::new(file_name);
+        let inventory = if is_yaml_file(path) {
+            Inventory::parse_yaml(contents, path).unwrap()
+        } else {
+            Inventory::parse_ini(contents, path).unwrap()
+        };
+
+        let host_names: Vec<&str> = inventory.hosts().iter().map(|h| h.name.as_str()).collect();
+        assert_eq!(host_names, vec!["master1", "worker1", "worker2"]);
+
+        let cluster: Vec<&str> = inventory.group_hosts("cluster").iter().map(|h| h.name.as_str()).collect();
+        assert_eq!(cluster, vec!["master1", "worker1", "worker2"]);
+        let workers: Vec<&str> = inventory.group_hosts("cluster_workers").iter().map(|h| h.name.as_str()).collect();
+        assert_eq!(workers, vec!["worker1", "worker2"]);
+
+        assert_eq!(inventory.host_var("master1", "ansible_host").unwrap(), "192.168.0.10");
+        assert_eq!(inventory.host_var("master1", "ansible_user").unwrap(), "rodrigo");
+        assert_eq!(inventory.host_var("worker2", "ansible_user").unwrap(), "pi");
+        assert!(inventory.group(UNGROUPED_GROUP).unwrap().hosts.is_empty());
+    }
+
+    #[rstest]
+    fn locations_are_recorded() {
+        let yaml = Inventory::parse_yaml(YAML_INVENTORY, Path::new("hosts.yaml")).unwrap();
+        assert_eq!(yaml.host("master1").unwrap().location.line, 3);
+        assert_eq!(yaml.group("cluster_workers").unwrap().location.line, 11);
+
+        let ini = Inventory::parse_ini(INI_INVENTORY, Path::new("hosts")).unwrap();
+        assert_eq!(ini.host("worker2").unwrap().location.line, 3);
+        assert_eq!(ini.group("cluster_managers").unwrap().hosts[0].location.line, 11);
+    }
+
+    #[rstest]
+    fn vars_directories_are_loaded() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("hosts"), INI_INVENTORY).unwrap();
+        fs::create_dir(dir.path().join("group_vars")).unwrap();
+        fs::write(dir.path().join("group_vars").join("cluster_workers.yml"), "ansible_become: true\n").unwrap();
+        fs::create_dir(dir.path().join("host_vars")).unwrap();
+        fs::write(dir.path().join("host_vars").join("worker1"), "ansible_port: 2222\n").unwrap();
+
+        let inventory = Inventory::load(&dir.path().join("hosts")).unwrap();
+        assert_eq!(inventory.host_var("worker1", "ansible_port").unwrap(), "2222");
+        assert_eq!(inventory.host_var("worker2", "ansible_become").unwrap(), "true");
+        assert!(inventory.host_var("master1", "ansible_become").is_none());
+    }
+}
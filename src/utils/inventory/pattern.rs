@@ -0,0 +1,175 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind};
+
+use log::warn;
+
+use crate::utils::inventory::{Host, Inventory, ALL_GROUP};
+
+impl Inventory {
+    /// Returns the hosts matching an Ansible host pattern, in inventory order.
+    ///
+    /// Patterns are made of host names, group names or wildcards (`*`, `?`),
+    /// separated by `:` or `,`. Terms starting with `&` intersect the result,
+    /// and terms starting with `!` exclude hosts from it.
+    pub fn resolve_pattern(&self, pattern: &str) -> Result<Vec<&Host>, Error> {
+        let mut included: HashSet<&str> = HashSet::new();
+        let mut intersections: Vec<HashSet<&str>> = Vec::new();
+        let mut excluded: HashSet<&str> = HashSet::new();
+
+        for term in split_pattern(pattern) {
+            if let Some(t) = term.strip_prefix('!') {
+                excluded.extend(self.match_term(t)?);
+            } else if let Some(t) = term.strip_prefix('&') {
+                intersections.push(self.match_term(t)?);
+            } else {
+                included.extend(self.match_term(&term)?);
+            }
+        }
+
+        Ok(self.hosts().iter()
+            .filter(|h| included.contains(h.name.as_str()))
+            .filter(|h| intersections.iter().all(|i| i.contains(h.name.as_str())))
+            .filter(|h| !excluded.contains(h.name.as_str()))
+            .collect())
+    }
+
+    fn match_term(&self, term: &str) -> Result<HashSet<&str>, Error> {
+        if term.starts_with('~') {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Regular expression host patterns ('{}') are not supported", term)));
+        }
+
+        let mut hosts: HashSet<&str> = HashSet::new();
+        if term == ALL_GROUP || term == "*" {
+            hosts.extend(self.hosts().iter().map(|h| h.name.as_str()));
+        } else if term.contains('*') || term.contains('?') {
+            for group in self.groups().iter().filter(|g| glob_matches(term, &g.name)) {
+                hosts.extend(self.group_hosts(&group.name).iter().map(|h| h.name.as_str()));
+            }
+            hosts.extend(self.hosts().iter().filter(|h| glob_matches(term, &h.name)).map(|h| h.name.as_str()));
+        } else if self.group(term).is_some() {
+            hosts.extend(self.group_hosts(term).iter().map(|h| h.name.as_str()));
+        } else if let Some(h) = self.host(term) {
+            hosts.insert(h.name.as_str());
+        }
+
+        if hosts.is_empty() {
+            warn!("Could not match supplied host pattern '{}'", term);
+        }
+
+        Ok(hosts)
+    }
+}
+
+/// Splits a host pattern in its terms, ignoring separators inside brackets.
+fn split_pattern(pattern: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+
+    for c in pattern.chars() {
+        match c {
+            '[' => {
+                depth += 1;
+                current.push(c);
+            },
+            ']' => {
+                depth -= 1;
+                current.push(c);
+            },
+            ':' | ',' if depth == 0 => {
+                if !current.trim().is_empty() {
+                    terms.push(current.trim().to_string());
+                }
+                current.clear();
+            },
+            _ => current.push(c)
+        }
+    }
+    if !current.trim().is_empty() {
+        terms.push(current.trim().to_string());
+    }
+
+    terms
+}
+
+/// Matches text against a shell-like wildcard pattern.
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    fn matches(p: &[char], t: &[char]) -> bool {
+        match p.first() {
+            None => t.is_empty(),
+            Some('*') => (0..=t.len()).any(|i| matches(&p[1..], &t[i..])),
+            Some('?') => !t.is_empty() && matches(&p[1..], &t[1..]),
+            Some(c) => t.first() == Some(c) && matches(&p[1..], &t[1..])
+        }
+    }
+
+    matches(&pattern, &text)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use rstest::rstest;
+    use crate::utils::inventory::Inventory;
+    use super::glob_matches;
+
+    const INVENTORY: &str = "\
+[cluster_managers]
+master1
+master2
+
+[cluster_workers]
+worker1
+worker2
+worker3
+
+[storage]
+worker3
+nas
+
+[cluster:children]
+cluster_managers
+cluster_workers
+";
+
+    #[rstest]
+    #[case("all", vec!["master1", "master2", "worker1", "worker2", "worker3", "nas"])]
+    #[case("cluster_workers", vec!["worker1", "worker2", "worker3"])]
+    #[case("worker2", vec!["worker2"])]
+    #[case("master1:worker1", vec!["master1", "worker1"])]
+    #[case("worker*", vec!["worker1", "worker2", "worker3"])]
+    #[case("cluster_*", vec!["master1", "master2", "worker1", "worker2", "worker3"])]
+    #[case("cluster:!cluster_managers", vec!["worker1", "worker2", "worker3"])]
+    #[case("cluster:&storage", vec!["worker3"])]
+    #[case("storage,master2", vec!["master2", "worker3", "nas"])]
+    #[case("unknown", vec![])]
+    fn host_patterns_are_correctly_resolved(
+        #[case] pattern: &str,
+        #[case] expected_hosts: Vec<&str>) {
+        let inventory = Inventory::parse_ini(INVENTORY, Path::new("hosts")).unwrap();
+        let hosts: Vec<&str> = inventory.resolve_pattern(pattern).unwrap().iter().map(|h| h.name.as_str()).collect();
+        assert_eq!(hosts, expected_hosts);
+    }
+
+    #[rstest]
+    #[case("worker*", "worker10", true)]
+    #[case("worker?", "worker1", true)]
+    #[case("worker?", "worker10", false)]
+    #[case("*1", "master1", true)]
+    #[case("master", "master1", false)]
+    fn wildcards_are_correctly_matched(
+        #[case] pattern: &str,
+        #[case] text: &str,
+        #[case] expected: bool) {
+        assert_eq!(glob_matches(pattern, text), expected);
+    }
+}
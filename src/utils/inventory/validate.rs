@@ -0,0 +1,172 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::utils::inventory::{Inventory, Location, ALL_GROUP, UNGROUPED_GROUP};
+
+/// Host where the Kubernetes control plane and other cluster-wide services are set up.
+pub const MAIN_MASTER_HOST: &str = "master1";
+/// Group containing all the hosts in the cluster.
+pub const CLUSTER_GROUP: &str = "cluster";
+/// Group containing the hosts acting as masters in the cluster.
+pub const CLUSTER_MANAGERS_GROUP: &str = "cluster_managers";
+/// Group containing the hosts that are not masters.
+pub const CLUSTER_WORKERS_GROUP: &str = "cluster_workers";
+
+const REQUIRED_GROUPS: [&str; 3] = [CLUSTER_GROUP, CLUSTER_MANAGERS_GROUP, CLUSTER_WORKERS_GROUP];
+
+/// A problem found in the inventory.
+#[derive(Clone, Debug)]
+pub struct ValidationIssue {
+    pub location: Option<Location>,
+    pub message: String
+}
+
+impl ValidationIssue {
+    fn new(location: Option<&Location>, message: String) -> Self {
+        ValidationIssue {
+            location: location.cloned(),
+            message
+        }
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(l) => write!(f, "{}: {}", l, self.message),
+            None => write!(f, "{}", self.message)
+        }
+    }
+}
+
+/// Checks the inventory follows the layout clusterctl needs: a `master1` host,
+/// `cluster`, `cluster_managers` and `cluster_workers` groups, and hosts with
+/// unique addresses.
+pub fn validate(inventory: &Inventory) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let file_location = inventory.files().first().map(|f| Location::new(f, 0));
+
+    for group in &REQUIRED_GROUPS {
+        if inventory.group(group).is_none() {
+            issues.push(ValidationIssue::new(file_location.as_ref(), format!("Required group '{}' is missing", group)));
+        }
+    }
+
+    match inventory.host(MAIN_MASTER_HOST) {
+        None => issues.push(ValidationIssue::new(file_location.as_ref(), format!("Required host '{}' is missing", MAIN_MASTER_HOST))),
+        Some(h) => {
+            if inventory.group(CLUSTER_MANAGERS_GROUP).is_some() && !is_in_group(inventory, &h.name, CLUSTER_MANAGERS_GROUP) {
+                issues.push(ValidationIssue::new(Some(&h.location),
+                    format!("Host '{}' must be in group '{}'", h.name, CLUSTER_MANAGERS_GROUP)));
+            }
+        }
+    }
+
+    if inventory.group(CLUSTER_GROUP).is_some() {
+        for group in &[CLUSTER_MANAGERS_GROUP, CLUSTER_WORKERS_GROUP] {
+            for host in inventory.group_hosts(group) {
+                if !is_in_group(inventory, &host.name, CLUSTER_GROUP) {
+                    issues.push(ValidationIssue::new(Some(&host.location),
+                        format!("Host '{}' is in group '{}' but not in group '{}'", host.name, group, CLUSTER_GROUP)));
+                }
+            }
+        }
+    }
+
+    let mut addresses: HashMap<String, &str> = HashMap::new();
+    for host in inventory.hosts() {
+        match inventory.host_var(&host.name, "ansible_host") {
+            None => issues.push(ValidationIssue::new(Some(&host.location),
+                format!("Host '{}' has no 'ansible_host' variable", host.name))),
+            Some(address) => {
+                if let Some(other) = addresses.get(&address) {
+                    issues.push(ValidationIssue::new(Some(&host.location),
+                        format!("Host '{}' has the same address ({}) as host '{}'", host.name, address, other)));
+                } else {
+                    addresses.insert(address, &host.name);
+                }
+            }
+        }
+    }
+
+    for host in inventory.group_hosts(CLUSTER_WORKERS_GROUP) {
+        if is_in_group(inventory, &host.name, CLUSTER_MANAGERS_GROUP) {
+            let location = inventory.group(CLUSTER_WORKERS_GROUP)
+                .and_then(|g| g.hosts.iter().find(|m| m.name == host.name))
+                .map(|m| &m.location)
+                .unwrap_or(&host.location);
+            issues.push(ValidationIssue::new(Some(location),
+                format!("Host '{}' is both in '{}' and '{}'", host.name, CLUSTER_MANAGERS_GROUP, CLUSTER_WORKERS_GROUP)));
+        }
+    }
+
+    for group in inventory.groups() {
+        if group.name != ALL_GROUP && group.name != UNGROUPED_GROUP && inventory.group_hosts(&group.name).is_empty() {
+            issues.push(ValidationIssue::new(Some(&group.location), format!("Group '{}' has no hosts", group.name)));
+        }
+    }
+
+    issues
+}
+
+fn is_in_group(inventory: &Inventory, host: &str, group: &str) -> bool {
+    inventory.group_hosts(group).iter().any(|h| h.name == host)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use rstest::rstest;
+    use crate::utils::inventory::Inventory;
+    use super::validate;
+
+    const VALID_INVENTORY: &str = "\
+master1 ansible_host=192.168.0.10
+worker1 ansible_host=192.168.0.11
+
+[cluster]
+master1
+worker1
+
+[cluster_managers]
+master1
+
+[cluster_workers]
+worker1
+";
+
+    #[rstest]
+    fn valid_inventory_has_no_issues() {
+        let inventory = Inventory::parse_ini(VALID_INVENTORY, Path::new("hosts")).unwrap();
+        assert!(validate(&inventory).is_empty());
+    }
+
+    #[rstest]
+    #[case(VALID_INVENTORY.replace("[cluster_workers]\nworker1", "[cluster_workers]\nworker1\nmaster1"),
+        "hosts:13: Host 'master1' is both in 'cluster_managers' and 'cluster_workers'")]
+    #[case(VALID_INVENTORY.replace("192.168.0.11", "192.168.0.10"),
+        "hosts:2: Host 'worker1' has the same address (192.168.0.10) as host 'master1'")]
+    #[case(VALID_INVENTORY.replace(" ansible_host=192.168.0.11", ""),
+        "hosts:2: Host 'worker1' has no 'ansible_host' variable")]
+    #[case(VALID_INVENTORY.replace("master1", "master2"),
+        "hosts: Required host 'master1' is missing")]
+    #[case(VALID_INVENTORY.replace("[cluster_managers]\nmaster1\n", ""),
+        "hosts: Required group 'cluster_managers' is missing")]
+    #[case(VALID_INVENTORY.replace("[cluster_managers]\nmaster1\n", "[cluster_managers]\n"),
+        "hosts:8: Group 'cluster_managers' has no hosts")]
+    #[case(VALID_INVENTORY.replace("[cluster]\nmaster1\nworker1\n", "[cluster]\nmaster1\n"),
+        "hosts:2: Host 'worker1' is in group 'cluster_workers' but not in group 'cluster'")]
+    fn inventory_problems_are_reported(
+        #[case] contents: String,
+        #[case] expected_issue: &str) {
+        let inventory = Inventory::parse_ini(&contents, Path::new("hosts")).unwrap();
+        let issues: Vec<String> = validate(&inventory).iter().map(|i| i.to_string()).collect();
+        assert!(issues.contains(&expected_issue.to_string()), "{:?}", issues);
+    }
+}
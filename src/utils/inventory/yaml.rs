@@ -0,0 +1,299 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::collections::HashMap;
+use std::io::Error;
+use std::mem;
+use std::path::Path;
+
+use log::warn;
+use serde_yaml::{Mapping, Value};
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, TScalarStyle};
+
+use crate::utils::inventory::{expand_host_range, parse_error, Inventory, Location};
+
+/// Contents of a YAML node.
+#[derive(Clone, Debug)]
+pub enum NodeKind {
+    /// Scalar value, and whether it was written without quotes
+    Scalar(String, bool),
+    Sequence(Vec<Node>),
+    Mapping(Vec<(Node, Node)>)
+}
+
+/// A YAML node together with the (1-based) line where it starts in the
+/// source document.
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub line: usize
+}
+
+impl Node {
+    pub fn is_null(&self) -> bool {
+        match &self.kind {
+            NodeKind::Scalar(s, true) => s == "~" || s == "null" || s.is_empty(),
+            _ => false
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.kind {
+            NodeKind::Scalar(s, _) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn entries(&self) -> Option<&[(Node, Node)]> {
+        match &self.kind {
+            NodeKind::Mapping(entries) => Some(entries),
+            _ => None
+        }
+    }
+
+    /// Looks up a key in a mapping node.
+    pub fn get(&self, key: &str) -> Option<&(Node, Node)> {
+        self.entries()
+            .and_then(|entries| entries.iter().find(|(k, _)| k.as_str() == Some(key)))
+    }
+
+    /// Converts the node into a plain YAML value, resolving scalar types.
+    pub fn to_value(&self) -> Value {
+        match &self.kind {
+            NodeKind::Scalar(s, false) => Value::String(s.clone()),
+            NodeKind::Scalar(_, true) if self.is_null() => Value::Null,
+            NodeKind::Scalar(s, true) => {
+                serde_yaml::from_str::<Value>(s).unwrap_or_else(|_| Value::String(s.clone()))
+            },
+            NodeKind::Sequence(items) => Value::Sequence(items.iter().map(Node::to_value).collect()),
+            NodeKind::Mapping(entries) => {
+                let mut mapping = Mapping::new();
+                for (k, v) in entries {
+                    mapping.insert(k.to_value(), v.to_value());
+                }
+                Value::Mapping(mapping)
+            }
+        }
+    }
+}
+
+/// Builds a tree of `Node`s from the parser events, keeping their positions.
+#[derive(Default)]
+struct Loader {
+    documents: Vec<Node>,
+    stack: Vec<(Node, usize)>,
+    keys: Vec<Option<Node>>,
+    anchors: HashMap<usize, Node>
+}
+
+impl Loader {
+    fn insert_node(&mut self, node: Node, anchor: usize) {
+        if anchor > 0 {
+            self.anchors.insert(anchor, node.clone());
+        }
+
+        match self.stack.last_mut() {
+            None => self.stack.push((node, anchor)),
+            Some((parent, _)) => match &mut parent.kind {
+                NodeKind::Sequence(items) => items.push(node),
+                NodeKind::Mapping(entries) => {
+                    let key = self.keys.last_mut().unwrap();
+                    match mem::take(key) {
+                        None => *key = Some(node),
+                        Some(k) => entries.push((k, node))
+                    }
+                },
+                NodeKind::Scalar(_, _) => unreachable!()
+            }
+        }
+    }
+}
+
+impl MarkedEventReceiver for Loader {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        let line = mark.line();
+        match event {
+            Event::DocumentEnd => {
+                if let Some((node, _)) = self.stack.pop() {
+                    self.documents.push(node);
+                }
+            },
+            Event::SequenceStart(anchor) => {
+                self.stack.push((Node { kind: NodeKind::Sequence(Vec::new()), line }, anchor));
+            },
+            Event::MappingStart(anchor) => {
+                self.stack.push((Node { kind: NodeKind::Mapping(Vec::new()), line }, anchor));
+                self.keys.push(None);
+            },
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Event::MappingEnd = event {
+                    self.keys.pop();
+                }
+                let (node, anchor) = self.stack.pop().unwrap();
+                self.insert_node(node, anchor);
+            },
+            Event::Scalar(value, style, anchor, _) => {
+                let plain = style == TScalarStyle::Plain;
+                self.insert_node(Node { kind: NodeKind::Scalar(value, plain), line }, anchor);
+            },
+            Event::Alias(anchor) => {
+                let node = self.anchors.get(&anchor).cloned()
+                    .unwrap_or(Node { kind: NodeKind::Scalar("~".to_string(), true), line });
+                self.insert_node(node, 0);
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Parses a YAML document, keeping track of the position of every node.
+pub fn load(contents: &str, file: &Path) -> Result<Option<Node>, Error> {
+    let mut loader = Loader::default();
+    let mut parser = Parser::new(contents.chars());
+    parser.load(&mut loader, false)
+        .map_err(|e| {
+            let location = Location::new(file, e.marker().line());
+            parse_error(&location, &e.to_string())
+        })?;
+
+    Ok(loader.documents.into_iter().next())
+}
+
+/// Parses an inventory in Ansible's YAML format, adding its hosts and groups
+/// to `inventory`.
+pub fn parse(inventory: &mut Inventory, contents: &str, file: &Path) -> Result<(), Error> {
+    let root = match load(contents, file)? {
+        Some(node) if !node.is_null() => node,
+        _ => return Ok(())
+    };
+
+    let entries = root.entries()
+        .ok_or_else(|| parse_error(&Location::new(file, root.line), "Inventory must be a mapping of groups"))?;
+    for (key, value) in entries {
+        parse_group(inventory, key, value, file)?;
+    }
+
+    Ok(())
+}
+
+fn parse_group(inventory: &mut Inventory, key: &Node, value: &Node, file: &Path) -> Result<(), Error> {
+    let location = Location::new(file, key.line);
+    let name = key.as_str()
+        .ok_or_else(|| parse_error(&location, "Group name must be a string"))?;
+    inventory.ensure_group(name, &location);
+
+    if value.is_null() {
+        return Ok(());
+    }
+    let entries = value.entries()
+        .ok_or_else(|| parse_error(&location, &format!("Group '{}' must be a mapping", name)))?;
+
+    for (section_key, section) in entries {
+        let section_location = Location::new(file, section_key.line);
+        if section.is_null() {
+            continue;
+        }
+
+        match section_key.as_str() {
+            Some("hosts") => {
+                let hosts = section.entries()
+                    .ok_or_else(|| parse_error(&section_location, &format!("Hosts of group '{}' must be a mapping", name)))?;
+                for (host_key, host_vars) in hosts {
+                    let host_location = Location::new(file, host_key.line);
+                    let pattern = host_key.as_str()
+                        .ok_or_else(|| parse_error(&host_location, "Host name must be a string"))?;
+                    let vars = node_to_variables(host_vars, &host_location)?;
+
+                    for host in expand_host_range(pattern).map_err(|e| parse_error(&host_location, &e))? {
+                        let h = inventory.ensure_host(&host, &host_location);
+                        for (k, v) in &vars {
+                            h.vars.insert(k.clone(), v.clone());
+                        }
+                        inventory.add_group_host(name, &host, &host_location);
+                    }
+                }
+            },
+
+            Some("vars") => {
+                for (k, v) in node_to_variables(section, &section_location)? {
+                    inventory.set_group_var(name, &k, v);
+                }
+            },
+
+            Some("children") => {
+                let children = section.entries()
+                    .ok_or_else(|| parse_error(&section_location, &format!("Children of group '{}' must be a mapping", name)))?;
+                for (child_key, child) in children {
+                    parse_group(inventory, child_key, child, file)?;
+                    if let Some(child_name) = child_key.as_str() {
+                        inventory.add_group_child(name, child_name, &Location::new(file, child_key.line));
+                    }
+                }
+            },
+
+            _ => warn!("{}: Skipping unexpected key in group '{}'", section_location, name)
+        }
+    }
+
+    Ok(())
+}
+
+fn node_to_variables(node: &Node, location: &Location) -> Result<Vec<(String, Value)>, Error> {
+    if node.is_null() {
+        return Ok(Vec::new());
+    }
+
+    let entries = node.entries()
+        .ok_or_else(|| parse_error(location, "Variables must be a mapping"))?;
+
+    Ok(entries.iter()
+        .filter_map(|(k, v)| k.as_str().map(|name| (name.to_string(), v.to_value())))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use rstest::rstest;
+    use serde_yaml::Value;
+    use crate::utils::inventory::Inventory;
+    use super::load;
+
+    #[rstest]
+    fn node_positions_are_recorded() {
+        let root = load("all:\n  hosts:\n    master1:\n", Path::new("hosts.yaml")).unwrap().unwrap();
+        let (key, value) = root.get("all").unwrap();
+        assert_eq!(key.line, 1);
+
+        let (hosts_key, hosts) = value.get("hosts").unwrap();
+        assert_eq!(hosts_key.line, 2);
+        let (host_key, host) = hosts.get("master1").unwrap();
+        assert_eq!(host_key.line, 3);
+        assert!(host.is_null());
+    }
+
+    #[rstest]
+    #[case("42", Value::from(42))]
+    #[case("'42'", Value::from("42"))]
+    #[case("true", Value::from(true))]
+    #[case("~", Value::Null)]
+    #[case("hello", Value::from("hello"))]
+    fn scalars_are_correctly_typed(
+        #[case] scalar: &str,
+        #[case] expected_value: Value) {
+        let root = load(&format!("key: {}\n", scalar), Path::new("vars.yaml")).unwrap().unwrap();
+        assert_eq!(root.get("key").unwrap().1.to_value(), expected_value);
+    }
+
+    #[rstest]
+    #[case("- master1\n")]
+    #[case("all:\n  hosts:\n    - master1\n")]
+    #[case("all:\n  hosts: [\n")]
+    fn invalid_inventories_are_rejected(#[case] contents: &str) {
+        assert!(Inventory::parse_yaml(contents, Path::new("hosts.yaml")).is_err());
+    }
+}
@@ -0,0 +1,321 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use log::error;
+use serde_yaml::{Mapping, Value};
+
+/// Sections of a kubeconfig file holding named entries.
+const NAMED_SECTIONS: [(&str, &str); 3] = [("clusters", "cluster"), ("users", "user"), ("contexts", "context")];
+
+/// Port haproxy balances the API servers on, behind the control plane virtual IP.
+const CONTROL_PLANE_VIP_PORT: &str = "8443";
+
+/// Kubeconfig used by kubectl by default: the first file in `$KUBECONFIG`,
+/// or `~/.kube/config`.
+pub fn default_path() -> Result<PathBuf, Error> {
+    if let Some(path) = env::var_os("KUBECONFIG").and_then(|v| env::split_paths(&v).next()) {
+        return Ok(path);
+    }
+
+    match env::var_os("HOME") {
+        Some(home) => Ok(Path::new(&home).join(".kube").join("config")),
+        None => {
+            let msg = "Could not find the home directory, use --file to choose where to write the kubeconfig";
+            error!("{}", msg);
+            Err(Error::new(ErrorKind::NotFound, msg))
+        }
+    }
+}
+
+/// Turns the admin kubeconfig generated by kubeadm into one usable from
+/// outside the cluster: the API server is reached through `server_address`,
+/// unless it already points at one of the control plane `endpoints`, and the
+/// cluster, user and context are all called `name`.
+pub fn rewrite(admin_conf: &str, server_address: &str, endpoints: &[String], name: &str) -> Result<Value, Error> {
+    let mut config = parse(admin_conf)?;
+
+    for (section, key) in NAMED_SECTIONS.iter() {
+        let entries = config.get_mut(&Value::from(*section))
+            .and_then(|v| v.as_sequence_mut())
+            .filter(|s| s.len() == 1)
+            .ok_or_else(|| invalid_config(&format!("expected a single entry in '{}'", section)))?;
+        let entry = entries[0].as_mapping_mut()
+            .ok_or_else(|| invalid_config(&format!("invalid entry in '{}'", section)))?;
+        entry.insert(Value::from("name"), Value::from(name));
+
+        let details = entry.get_mut(&Value::from(*key)).and_then(|v| v.as_mapping_mut());
+        match *key {
+            "cluster" => {
+                let server = details.and_then(|d| d.get_mut(&Value::from("server")))
+                    .ok_or_else(|| invalid_config("the cluster has no server"))?;
+                let address = server.as_str()
+                    .map(|s| if points_at(s, endpoints) { s.to_string() } else { replace_server_host(s, server_address) })
+                    .ok_or_else(|| invalid_config("the cluster server is not a URL"))?;
+                *server = Value::from(address);
+            },
+            "context" => {
+                let details = details.ok_or_else(|| invalid_config("the context has no details"))?;
+                details.insert(Value::from("cluster"), Value::from(name));
+                details.insert(Value::from("user"), Value::from(name));
+            },
+            _ => {}
+        }
+    }
+
+    config.insert(Value::from("current-context"), Value::from(name));
+    Ok(Value::Mapping(config))
+}
+
+/// Adds the entries of `config` to the `existing` kubeconfig, replacing the
+/// ones with the same name. The current context is only changed if there
+/// wasn't one already.
+pub fn merge(existing: &str, config: &Value) -> Result<Value, Error> {
+    let mut merged = if existing.trim().is_empty() { Mapping::new() } else { parse(existing)? };
+    let config = config.as_mapping().ok_or_else(|| invalid_config("not a mapping"))?;
+
+    for (key, default) in [("apiVersion", "v1"), ("kind", "Config")].iter() {
+        if !merged.contains_key(&Value::from(*key)) {
+            merged.insert(Value::from(*key), Value::from(*default));
+        }
+    }
+
+    for (section, _) in NAMED_SECTIONS.iter() {
+        let new_entries = config.get(&Value::from(*section))
+            .and_then(|v| v.as_sequence())
+            .cloned()
+            .unwrap_or_default();
+        let entries = merged.entry(Value::from(*section))
+            .or_insert_with(|| Value::Sequence(Vec::new()));
+        if entries.is_null() {
+            *entries = Value::Sequence(Vec::new());
+        }
+        let entries = entries.as_sequence_mut()
+            .ok_or_else(|| invalid_config(&format!("'{}' is not a list", section)))?;
+
+        for entry in new_entries {
+            entries.retain(|e| e.get("name") != entry.get("name"));
+            entries.push(entry);
+        }
+    }
+
+    let has_current_context = merged.get(&Value::from("current-context"))
+        .and_then(|v| v.as_str())
+        .is_some_and(|c| !c.is_empty());
+    if !has_current_context {
+        if let Some(context) = config.get(&Value::from("current-context")) {
+            merged.insert(Value::from("current-context"), context.clone());
+        }
+    }
+
+    Ok(Value::Mapping(merged))
+}
+
+/// Writes a kubeconfig readable only by the current user, as it holds the
+/// cluster credentials.
+pub fn save(path: &Path, config: &Value) -> Result<(), Error> {
+    let contents = serde_yaml::to_string(config)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents.as_bytes())
+}
+
+fn parse(contents: &str) -> Result<Mapping, Error> {
+    match serde_yaml::from_str::<Value>(contents) {
+        Ok(Value::Mapping(m)) => Ok(m),
+        Ok(_) => Err(invalid_config("not a mapping")),
+        Err(e) => Err(invalid_config(&e.to_string()))
+    }
+}
+
+/// Splits a server URL in its scheme (with the `://` separator), host and
+/// port, if any.
+fn split_server(server: &str) -> (String, &str, Option<&str>) {
+    let (scheme, authority) = match server.split_once("://") {
+        Some((s, a)) => (format!("{}://", s), a),
+        None => (String::new(), server)
+    };
+    let authority = authority.trim_end_matches('/');
+
+    let (host, port) = if let Some(host) = authority.strip_prefix('[') {
+        match host.split_once("]:") {
+            Some((h, p)) => (h, Some(p)),
+            None => (host.trim_end_matches(']'), None)
+        }
+    } else {
+        match authority.rsplit_once(':') {
+            Some((h, p)) => (h, Some(p)),
+            None => (authority, None)
+        }
+    };
+
+    (scheme, host, port)
+}
+
+/// Whether the server URL points at one of `endpoints`, given as a host or
+/// as `host:port`, or at the control plane virtual IP, which is known by its
+/// port when it was only given on the command line.
+fn points_at(server: &str, endpoints: &[String]) -> bool {
+    let (_, host, port) = split_server(server);
+    let authority = port.map(|p| format!("{}:{}", host, p));
+
+    port == Some(CONTROL_PLANE_VIP_PORT) || endpoints.iter().any(|e| e == host || Some(e) == authority.as_ref())
+}
+
+/// Replaces the host in a server URL like `https://10.0.0.1:6443`, keeping
+/// its scheme and port.
+fn replace_server_host(server: &str, address: &str) -> String {
+    let (scheme, _, port) = split_server(server);
+    let host = if address.contains(':') { format!("[{}]", address) } else { address.to_string() };
+
+    match port {
+        Some(p) => format!("{}{}:{}", scheme, host, p),
+        None => format!("{}{}", scheme, host)
+    }
+}
+
+fn invalid_config(reason: &str) -> Error {
+    let msg = format!("Invalid kubeconfig: {}", reason);
+    error!("{}", msg);
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use serde_yaml::Value;
+    use super::{merge, points_at, replace_server_host, rewrite};
+
+    const ADMIN_CONF: &str = "\
+apiVersion: v1
+clusters:
+- cluster:
+    certificate-authority-data: Q0EK
+    server: https://192.168.1.10:6443
+  name: kubernetes
+contexts:
+- context:
+    cluster: kubernetes
+    user: kubernetes-admin
+  name: kubernetes-admin@kubernetes
+current-context: kubernetes-admin@kubernetes
+kind: Config
+preferences: {}
+users:
+- name: kubernetes-admin
+  user:
+    client-certificate-data: Q0VSVAo=
+    client-key-data: S0VZCg==
+";
+
+    fn names(config: &Value, section: &str) -> Vec<String> {
+        config[section].as_sequence().unwrap().iter()
+            .map(|e| e["name"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[rstest]
+    #[case("https://192.168.1.10:6443", "master1.example.com", "https://master1.example.com:6443")]
+    #[case("https://[fd00::10]:6443/", "fd00::20", "https://[fd00::20]:6443")]
+    #[case("https://10.0.0.1", "10.0.0.2", "https://10.0.0.2")]
+    fn server_host_is_replaced(#[case] server: &str, #[case] address: &str, #[case] expected: &str) {
+        assert_eq!(replace_server_host(server, address), expected);
+    }
+
+    #[rstest]
+    #[case("https://192.168.1.100:8443", &["192.168.1.100"], true)]
+    #[case("https://k8s.example.com:6443", &["k8s.example.com:6443"], true)]
+    #[case("https://[fd00::100]:8443", &["fd00::100"], true)]
+    #[case("https://192.168.1.10:6443", &["192.168.1.100"], false)]
+    #[case("https://192.168.1.10:6443", &["192.168.1.10:8443"], false)]
+    #[case("https://192.168.1.10:6443", &[], false)]
+    #[case("https://192.168.1.200:8443", &[], true)]
+    fn servers_pointing_at_endpoints_are_detected(#[case] server: &str, #[case] endpoints: &[&str], #[case] expected: bool) {
+        let endpoints: Vec<String> = endpoints.iter().map(|e| e.to_string()).collect();
+        assert_eq!(points_at(server, &endpoints), expected);
+    }
+
+    #[rstest]
+    #[case(&[], "https://10.0.0.5:6443")]
+    #[case(&["192.168.1.10:6443"], "https://192.168.1.10:6443")]
+    fn server_is_kept_when_it_points_at_an_endpoint(#[case] endpoints: &[&str], #[case] expected: &str) {
+        let endpoints: Vec<String> = endpoints.iter().map(|e| e.to_string()).collect();
+        let config = rewrite(ADMIN_CONF, "10.0.0.5", &endpoints, "homelab").unwrap();
+        assert_eq!(config["clusters"][0]["cluster"]["server"].as_str(), Some(expected));
+    }
+
+    #[rstest]
+    fn admin_kubeconfig_is_rewritten() {
+        let config = rewrite(ADMIN_CONF, "10.0.0.5", &[], "homelab").unwrap();
+
+        assert_eq!(config["clusters"][0]["cluster"]["server"].as_str(), Some("https://10.0.0.5:6443"));
+        assert_eq!(config["clusters"][0]["cluster"]["certificate-authority-data"].as_str(), Some("Q0EK"));
+        assert_eq!(config["contexts"][0]["context"]["cluster"].as_str(), Some("homelab"));
+        assert_eq!(config["contexts"][0]["context"]["user"].as_str(), Some("homelab"));
+        assert_eq!(config["current-context"].as_str(), Some("homelab"));
+        for section in &["clusters", "users", "contexts"] {
+            assert_eq!(names(&config, section), vec!["homelab"]);
+        }
+    }
+
+    #[rstest]
+    fn kubeconfig_is_merged_replacing_entries() {
+        let existing = "\
+apiVersion: v1
+kind: Config
+clusters:
+- cluster: {server: https://old:6443}
+  name: homelab
+- cluster: {server: https://cloud:443}
+  name: cloud
+users:
+- name: cloud
+  user: {token: abc}
+contexts:
+- context: {cluster: cloud, user: cloud}
+  name: cloud
+current-context: cloud
+";
+        let config = rewrite(ADMIN_CONF, "10.0.0.5", &[], "homelab").unwrap();
+        let merged = merge(existing, &config).unwrap();
+
+        assert_eq!(names(&merged, "clusters"), vec!["cloud", "homelab"]);
+        assert_eq!(names(&merged, "users"), vec!["cloud", "homelab"]);
+        assert_eq!(names(&merged, "contexts"), vec!["cloud", "homelab"]);
+        assert_eq!(merged["clusters"][1]["cluster"]["server"].as_str(), Some("https://10.0.0.5:6443"));
+        assert_eq!(merged["current-context"].as_str(), Some("cloud"));
+    }
+
+    #[rstest]
+    fn kubeconfig_is_merged_into_empty_file() {
+        let config = rewrite(ADMIN_CONF, "10.0.0.5", &[], "homelab").unwrap();
+        let merged = merge("", &config).unwrap();
+
+        assert_eq!(merged["kind"].as_str(), Some("Config"));
+        assert_eq!(names(&merged, "contexts"), vec!["homelab"]);
+        assert_eq!(merged["current-context"].as_str(), Some("homelab"));
+    }
+
+    #[rstest]
+    fn invalid_kubeconfig_is_rejected() {
+        assert!(rewrite("clusters: []\n", "10.0.0.5", &[], "homelab").is_err());
+    }
+}
@@ -4,4 +4,8 @@
  * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
  */
 
+pub mod inventory;
+pub mod kubeconfig;
+pub mod output;
 pub mod settings;
+pub mod wol;
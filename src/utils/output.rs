@@ -0,0 +1,85 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::io::{self, Error, ErrorKind, Write};
+
+use serde::Serialize;
+
+use crate::utils::settings::OutputFormat;
+
+/// A record that can be shown as a row in a table.
+pub trait TableRow {
+    fn headers() -> Vec<&'static str>;
+    fn cells(&self) -> Vec<String>;
+}
+
+/// Prints a list of records in the requested format.
+pub fn print_records<T: Serialize + TableRow>(format: OutputFormat, records: &[T]) -> Result<(), Error> {
+    let text = match format {
+        OutputFormat::Json => serde_json::to_string_pretty(records)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?,
+        OutputFormat::Yaml => serde_yaml::to_string(records)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?,
+        OutputFormat::Table => {
+            let rows: Vec<Vec<String>> = records.iter().map(TableRow::cells).collect();
+            render_table(&T::headers(), &rows)
+        }
+    };
+
+    writeln!(io::stdout(), "{}", text.trim_end())
+}
+
+/// Renders rows as a table with aligned columns. Cells spanning several lines
+/// are continued in extra rows.
+pub fn render_table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut lines: Vec<Vec<String>> = vec![headers.iter().map(|h| h.to_uppercase()).collect()];
+    for row in rows {
+        let cell_lines: Vec<Vec<&str>> = row.iter().map(|c| c.lines().collect()).collect();
+        let height = cell_lines.iter().map(Vec::len).max().unwrap_or(0).max(1);
+        for i in 0..height {
+            lines.push(cell_lines.iter().map(|c| c.get(i).unwrap_or(&"").to_string()).collect());
+        }
+    }
+
+    let mut widths = vec![0; headers.len()];
+    for line in &lines {
+        for (i, cell) in line.iter().enumerate().take(widths.len()) {
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+
+    let mut text = String::new();
+    for line in lines {
+        let cells: Vec<String> = line.iter().enumerate()
+            .map(|(i, cell)| format!("{:width$}", cell, width = widths.get(i).copied().unwrap_or(0)))
+            .collect();
+        text.push_str(cells.join("  ").trim_end());
+        text.push('\n');
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use super::render_table;
+
+    #[rstest]
+    fn tables_are_aligned() {
+        let rows = vec![
+            vec!["master1".to_string(), "ok".to_string(), "up 3 days".to_string()],
+            vec!["worker10".to_string(), "failed".to_string(), "line 1\nline 2".to_string()]
+        ];
+
+        assert_eq!(render_table(&["host", "status", "output"], &rows), "\
+HOST      STATUS  OUTPUT
+master1   ok      up 3 days
+worker10  failed  line 1
+                  line 2
+");
+    }
+}
@@ -4,7 +4,10 @@
  * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
  */
 
+use std::net::IpAddr;
+
 use clap::{Clap, crate_version, crate_authors, crate_description};
+use strum_macros::EnumString;
 
 #[derive(Clap)]
 #[clap(version = crate_version!(), author = crate_authors!(), about = crate_description!())]
@@ -18,10 +21,40 @@ pub struct ClusterSettings {
     #[clap(short = 'p', long, about = "Host pattern. If not specified, all machines in the cluster is assumed")]
     pub host_pattern: Option<String>,
 
+    #[clap(short, long, possible_values = &["json", "yaml", "table"], about = "Print the results of each host in the given format, instead of Ansible's output")]
+    pub output: Option<OutputFormat>,
+
+    #[clap(long, default_value = "ansible", possible_values = &["ansible", "ssh"], about = "Backend used to run commands on the machines. The ssh backend only supports the run, uptime, ping, copy and fetch commands")]
+    pub backend: Backend,
+
+    #[clap(long, about = "Print the commands that would be run, with their environment and playbooks, instead of running them")]
+    pub dry_run: bool,
+
+    #[clap(long, requires = "dry-run", about = "With --dry-run, also run playbooks with 'ansible-playbook --check --diff' to show the changes they would make")]
+    pub check: bool,
+
+    #[clap(long, env = "CLUSTERCTL_SERVICES_DIR", about = "Directory with user-defined services, one per subdirectory with a service.yaml manifest")]
+    pub services_dir: Option<String>,
+
     #[clap(subcommand)]
     pub subcommand: SubCommand
 }
 
+#[derive(Clone, Copy, Debug, EnumString, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum OutputFormat {
+    Json,
+    Yaml,
+    Table
+}
+
+#[derive(Clone, Copy, Debug, EnumString, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum Backend {
+    Ansible,
+    Ssh
+}
+
 #[derive(Clap, Debug)]
 pub enum SubCommand {
     #[clap(about = "Copy local files to machines in the cluster")]
@@ -30,20 +63,24 @@ pub enum SubCommand {
     Fetch(CopyCommand),
     #[clap(about = "Commands to operate on the configured inventory")]
     Inventory(InventoryCommand),
+    #[clap(about = "Fetch the Kubernetes cluster credentials and merge them into the local kubeconfig")]
+    Kubeconfig(KubeconfigCommand),
     #[clap(about = "Ping all machines in the cluster to check they're alive and reachable")]
     Ping(GenericCommand),
+    #[clap(about = "Power on machines in the cluster with Wake-on-LAN")]
+    Poweron(PoweronCommand),
     #[clap(about = "Reboot all machines in the cluster")]
-    Reboot(GenericCommand),
+    Reboot(RebootCommand),
     #[clap(about = "Run a command on all machines in the cluster")]
     Run(RunCommand),
     #[clap(about = "Commands to operate services on the cluster")]
     Service(ServiceCommand),
     #[clap(about = "Shut down machines in the cluster")]
-    Shutdown(GenericCommand),
+    Shutdown(ShutdownCommand),
     #[clap(about = "Open a secure shell connection to a machine on the cluster")]
-    Ssh(GenericCommand),
+    Ssh(SshCommand),
     #[clap(about = "Perform OS and apps updates on all the machines in the cluster")]
-    Update(GenericCommand),
+    Update(UpdateCommand),
     #[clap(about = "Show how long machines in the cluster have been running")]
     Uptime(GenericCommand)
 }
@@ -63,6 +100,63 @@ pub struct CopyCommand {
     pub dest: String
 }
 
+#[derive(Clap, Debug)]
+pub struct SshCommand {
+    #[clap(about = "Host to connect to. Partial names are matched against the inventory, and a list to pick from is shown when several hosts match")]
+    pub host: Option<String>
+}
+
+#[derive(Clap, Debug)]
+pub struct PoweronCommand {
+    #[clap(long, about = "Wait for the machines to answer ping, and report which ones came up")]
+    pub wait: bool,
+
+    #[clap(long, value_name = "SECONDS", default_value = "300", about = "How long to wait for the machines to come up")]
+    pub timeout: u64,
+
+    #[clap(long, value_name = "ADDRESS", default_value = "255.255.255.255", about = "Broadcast address of the network the Wake-on-LAN packets are sent to")]
+    pub broadcast: IpAddr,
+
+    #[clap(long, default_value = "9", about = "UDP port the Wake-on-LAN packets are sent to")]
+    pub port: u16
+}
+
+#[derive(Clap, Debug)]
+pub struct RebootCommand {
+    #[clap(long, about = "Reboot all the machines at once, without draining the Kubernetes nodes first")]
+    pub force: bool
+}
+
+#[derive(Clap, Debug)]
+pub struct ShutdownCommand {
+    #[clap(long, about = "Shut down all the machines at once, without asking before taking down the last manager")]
+    pub force: bool
+}
+
+#[derive(Clap, Debug)]
+pub struct UpdateCommand {
+    #[clap(long, about = "List the packages that would be upgraded on each machine, without upgrading them")]
+    pub check: bool,
+
+    #[clap(long, value_name = "PACKAGE", multiple_occurrences = true, number_of_values = 1, about = "Package to leave out of the update. Can be specified several times")]
+    pub exclude: Vec<String>,
+
+    #[clap(long, conflicts_with = "check", about = "Update the machines in batches, workers before managers, rebooting the ones that need it before moving on")]
+    pub rolling: bool,
+
+    #[clap(long, value_name = "SIZE", requires = "rolling", about = "Number of machines updated at once in a rolling update (default: 1)")]
+    pub batch: Option<usize>
+}
+
+#[derive(Clap, Debug)]
+pub struct KubeconfigCommand {
+    #[clap(long, default_value = "clusterctl", about = "Name of the context, cluster and user added to the kubeconfig")]
+    pub context: String,
+
+    #[clap(long, value_name = "FILE", about = "Write the kubeconfig to this file, instead of merging it into $KUBECONFIG or ~/.kube/config")]
+    pub file: Option<String>
+}
+
 #[derive(Clap, Debug)]
 pub struct InventoryCommand {
     #[clap(subcommand)]
@@ -75,12 +169,58 @@ pub enum InventorySubCommand {
     List(InventoryCommandOptions),
 
     #[clap(about = "Show details about all machines in the inventory")]
-    Show(InventoryCommandOptions)
+    Show(InventoryCommandOptions),
+
+    #[clap(about = "Check the inventory has the layout required by clusterctl")]
+    Validate(InventoryCommandOptions),
+
+    #[clap(about = "Add a host to the inventory")]
+    AddHost(InventoryAddHostOptions),
+
+    #[clap(about = "Remove a host from the inventory")]
+    RemoveHost(InventoryHostOptions),
+
+    #[clap(about = "Move a host between the cluster managers and workers groups")]
+    MoveHost(InventoryMoveHostOptions)
 }
 
 #[derive(Clap, Debug)]
 pub struct InventoryCommandOptions;
 
+#[derive(Clap, Debug)]
+pub struct InventoryAddHostOptions {
+    #[clap(about = "Name of the host")]
+    pub host: String,
+
+    #[clap(short, long, about = "IP address or DNS name used to connect to the host")]
+    pub address: String,
+
+    #[clap(short, long = "group", multiple_occurrences = true, number_of_values = 1, about = "Group to add the host to. Can be specified several times (default: cluster_workers)")]
+    pub groups: Vec<String>
+}
+
+#[derive(Clap, Debug)]
+pub struct InventoryHostOptions {
+    #[clap(about = "Name of the host")]
+    pub host: String
+}
+
+#[derive(Clap, Debug)]
+pub struct InventoryMoveHostOptions {
+    #[clap(about = "Name of the host")]
+    pub host: String,
+
+    #[clap(long, possible_values = &["managers", "workers"], about = "Role the host should have in the cluster")]
+    pub to: ClusterRole
+}
+
+#[derive(Clone, Copy, Debug, EnumString, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum ClusterRole {
+    Managers,
+    Workers
+}
+
 #[derive(Clap, Debug)]
 pub struct RunCommand {
     pub command: String,
@@ -100,23 +240,85 @@ pub struct ServiceCommand {
 
 #[derive(Clap, Debug)]
 pub enum ServiceSubCommand {
-    #[clap(about = "Deploy a service on the cluster")]
-    Deploy(ServiceCommandOptions),
+    #[clap(about = "Deploy services on the cluster, with the services they depend on")]
+    Deploy(ServiceDeployOptions),
     
     #[clap(about = "Delete a service from the cluster")]
-    Delete(ServiceCommandOptions),
+    Delete(ServiceDeleteOptions),
+
+    #[clap(about = "Show the status of a service on the cluster")]
+    Status(ServiceCommandOptions),
+
+    #[clap(about = "Upgrade a service on the cluster")]
+    Upgrade(ServiceUpgradeOptions),
+
+    #[clap(about = "Add the machines matched by --host-pattern to a service already deployed on the cluster")]
+    Join(ServiceCommandOptions),
+
+    #[clap(about = "Remove the machines matched by --host-pattern from a service, leaving the rest of the cluster untouched")]
+    Leave(ServiceCommandOptions),
 
     #[clap(about = "List services running in the cluster machines")]
-    List(GenericCommandOptions)
+    List(GenericCommandOptions),
+
+    #[clap(about = "List the services that can be deployed on the cluster")]
+    ListAvailable(GenericCommandOptions)
 }
 
 #[derive(Clap, Debug)]
 pub struct ServiceCommandOptions {
-    #[clap(about = "Service name ('kubernetes', 'docker', ...)")]
-    pub service: String
+    #[clap(about = "Service name ('kubernetes', 'docker', ...). Run 'service list-available' to see all of them")]
+    pub service: String,
+
+    #[clap(long = "set", value_name = "KEY=VALUE", parse(try_from_str = parse_key_value), multiple_occurrences = true, number_of_values = 1, about = "Set a service variable, as key=value. Can be specified several times")]
+    pub values: Vec<(String, String)>,
+
+    #[clap(long = "values", value_name = "FILE", multiple_occurrences = true, number_of_values = 1, about = "YAML file with values for the service variables. Can be specified several times")]
+    pub values_files: Vec<String>
+}
+
+#[derive(Clap, Debug)]
+pub struct ServiceDeployOptions {
+    #[clap(required = true, about = "Services to deploy ('kubernetes', 'docker', ...). Run 'service list-available' to see all of them")]
+    pub services: Vec<String>,
+
+    #[clap(long = "set", value_name = "KEY=VALUE", parse(try_from_str = parse_key_value), multiple_occurrences = true, number_of_values = 1, about = "Set a service variable, as key=value. Can be specified several times")]
+    pub values: Vec<(String, String)>,
+
+    #[clap(long = "values", value_name = "FILE", multiple_occurrences = true, number_of_values = 1, about = "YAML file with values for the service variables. Can be specified several times")]
+    pub values_files: Vec<String>,
+
+    #[clap(long, about = "Deploy the given services even if they are already installed. Their dependencies are only deployed if missing")]
+    pub redeploy: bool
+}
+
+#[derive(Clap, Debug)]
+pub struct ServiceDeleteOptions {
+    #[clap(flatten)]
+    pub service_options: ServiceCommandOptions,
+
+    #[clap(long, about = "Also delete the services this one depends on, like its container runtime")]
+    pub with_dependencies: bool
+}
+
+#[derive(Clap, Debug)]
+pub struct ServiceUpgradeOptions {
+    #[clap(flatten)]
+    pub service_options: ServiceCommandOptions,
+
+    #[clap(long, about = "Version to upgrade the service to")]
+    pub version: Option<String>
+}
+
+fn parse_key_value(text: &str) -> Result<(String, String), String> {
+    match text.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.to_string())),
+        _ => Err(format!("Expected key=value, found '{}'", text))
+    }
 }
 
 #[cfg(test)]
+#[allow(unused_variables)]
 mod tests {
     use clap::Clap;
     use log::Level;
@@ -142,12 +344,225 @@ mod tests {
         assert!(matches!(verbosity_level, expected_verbosity));
     }
 
+    #[rstest]
+    #[case("clusterctl --inventory /tmp/inventory.yaml ping", None)]
+    #[case("clusterctl --inventory /tmp/inventory.yaml --output json ping", Some(OutputFormat::Json))]
+    #[case("clusterctl --inventory /tmp/inventory.yaml -o yaml uptime", Some(OutputFormat::Yaml))]
+    #[case("clusterctl --inventory /tmp/inventory.yaml --output table service list", Some(OutputFormat::Table))]
+    fn output_format_is_correctly_parsed(
+        #[case] command_line: &str,
+        #[case] expected_output: Option<OutputFormat>) {
+        let args: Vec<&str> = command_line.split(' ').collect();
+        let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
+
+        assert_eq!(settings.output, expected_output);
+    }
+
+    #[rstest]
+    #[case("clusterctl ping", Backend::Ansible)]
+    #[case("clusterctl --backend ansible ping", Backend::Ansible)]
+    #[case("clusterctl --backend ssh uptime", Backend::Ssh)]
+    fn backend_is_correctly_parsed(
+        #[case] command_line: &str,
+        #[case] expected_backend: Backend) {
+        let args: Vec<&str> = command_line.split(' ').collect();
+        let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
+
+        assert_eq!(settings.backend, expected_backend);
+    }
+
+    #[rstest]
+    #[case("clusterctl ping", false, false)]
+    #[case("clusterctl --dry-run ping", true, false)]
+    #[case("clusterctl --dry-run --check service deploy docker", true, true)]
+    fn dry_run_is_correctly_parsed(
+        #[case] command_line: &str,
+        #[case] expected_dry_run: bool,
+        #[case] expected_check: bool) {
+        let args: Vec<&str> = command_line.split(' ').collect();
+        let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
+
+        assert_eq!(settings.dry_run, expected_dry_run);
+        assert_eq!(settings.check, expected_check);
+    }
+
+    #[rstest]
+    fn services_dir_is_correctly_parsed() {
+        let args = vec!["clusterctl", "--services-dir", "/etc/clusterctl/services", "service", "list-available"];
+        let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
+
+        assert_eq!(settings.services_dir.unwrap(), "/etc/clusterctl/services");
+    }
+
+    #[rstest]
+    #[case("clusterctl service deploy kubernetes", vec![], vec![])]
+    #[case("clusterctl service deploy kubernetes --set pod_network_cidr=10.10.0.0/16 --set kubeconfig_user=pi --values values.yaml",
+           vec![("pod_network_cidr", "10.10.0.0/16"), ("kubeconfig_user", "pi")], vec!["values.yaml"])]
+    #[case("clusterctl service delete docker --set docker_users= --values a.yaml --values b.yaml",
+           vec![("docker_users", "")], vec!["a.yaml", "b.yaml"])]
+    fn service_values_are_correctly_parsed(
+        #[case] command_line: &str,
+        #[case] expected_values: Vec<(&str, &str)>,
+        #[case] expected_files: Vec<&str>) {
+        let args: Vec<&str> = command_line.split(' ').collect();
+        let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
+
+        if let SubCommand::Service(ref sc) = settings.subcommand {
+            let (values, values_files) = match sc.subcommand {
+                ServiceSubCommand::Deploy(ref o) => (&o.values, &o.values_files),
+                ServiceSubCommand::Delete(ref o) => (&o.service_options.values, &o.service_options.values_files),
+                _ => panic!("Command {:?} is wrong", sc.subcommand)
+            };
+            let values: Vec<(&str, &str)> = values.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+            assert_eq!(values, expected_values);
+            assert_eq!(values_files, &expected_files);
+        } else {
+            panic!("Command {:?} is wrong", settings.subcommand);
+        }
+    }
+
+    #[rstest]
+    #[case("clusterctl service upgrade kubernetes", None)]
+    #[case("clusterctl service upgrade kubernetes --version 1.22.2", Some("1.22.2"))]
+    fn service_upgrade_version_is_correctly_parsed(#[case] command_line: &str, #[case] expected_version: Option<&str>) {
+        let args: Vec<&str> = command_line.split(' ').collect();
+        let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
+
+        if let SubCommand::Service(ServiceCommand { subcommand: ServiceSubCommand::Upgrade(ref options) }) = settings.subcommand {
+            assert_eq!(options.service_options.service, "kubernetes");
+            assert_eq!(options.version.as_deref(), expected_version);
+        } else {
+            panic!("Command {:?} is wrong", settings.subcommand);
+        }
+    }
+
+    #[rstest]
+    #[case("clusterctl kubeconfig", "clusterctl", None)]
+    #[case("clusterctl kubeconfig --context homelab --file homelab.yaml", "homelab", Some("homelab.yaml"))]
+    fn kubeconfig_options_are_correctly_parsed(
+        #[case] command_line: &str,
+        #[case] expected_context: &str,
+        #[case] expected_file: Option<&str>) {
+        let args: Vec<&str> = command_line.split(' ').collect();
+        let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
+
+        if let SubCommand::Kubeconfig(ref kc) = settings.subcommand {
+            assert_eq!(kc.context, expected_context);
+            assert_eq!(kc.file.as_deref(), expected_file);
+        } else {
+            panic!("Command {:?} is wrong", settings.subcommand);
+        }
+    }
+
+    #[rstest]
+    #[case("clusterctl poweron", false, 300, "255.255.255.255")]
+    #[case("clusterctl poweron --wait --timeout 60 --broadcast 192.168.1.255", true, 60, "192.168.1.255")]
+    fn poweron_options_are_correctly_parsed(
+        #[case] command_line: &str,
+        #[case] expected_wait: bool,
+        #[case] expected_timeout: u64,
+        #[case] expected_broadcast: &str) {
+        let args: Vec<&str> = command_line.split(' ').collect();
+        let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
+
+        if let SubCommand::Poweron(ref pc) = settings.subcommand {
+            assert_eq!(pc.wait, expected_wait);
+            assert_eq!(pc.timeout, expected_timeout);
+            assert_eq!(pc.broadcast.to_string(), expected_broadcast);
+            assert_eq!(pc.port, 9);
+        } else {
+            panic!("Command {:?} is wrong", settings.subcommand);
+        }
+    }
+
+    #[rstest]
+    #[case("clusterctl reboot", false)]
+    #[case("clusterctl reboot --force", true)]
+    #[case("clusterctl shutdown", false)]
+    #[case("clusterctl shutdown --force", true)]
+    fn power_options_are_correctly_parsed(#[case] command_line: &str, #[case] expected_force: bool) {
+        let args: Vec<&str> = command_line.split(' ').collect();
+        let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
+
+        match settings.subcommand {
+            SubCommand::Reboot(ref rc) => assert_eq!(rc.force, expected_force),
+            SubCommand::Shutdown(ref sc) => assert_eq!(sc.force, expected_force),
+            _ => panic!("Command {:?} is wrong", settings.subcommand)
+        }
+    }
+
+    #[rstest]
+    #[case("clusterctl update", false, vec![])]
+    #[case("clusterctl update --check", true, vec![])]
+    #[case("clusterctl update --exclude kubeadm --exclude kubelet", false, vec!["kubeadm", "kubelet"])]
+    fn update_options_are_correctly_parsed(
+        #[case] command_line: &str,
+        #[case] expected_check: bool,
+        #[case] expected_exclude: Vec<&str>) {
+        let args: Vec<&str> = command_line.split(' ').collect();
+        let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
+
+        if let SubCommand::Update(ref uc) = settings.subcommand {
+            assert_eq!(uc.check, expected_check);
+            assert_eq!(uc.exclude, expected_exclude);
+        } else {
+            panic!("Command {:?} is wrong", settings.subcommand);
+        }
+    }
+
+    #[rstest]
+    #[case("clusterctl update --rolling", None)]
+    #[case("clusterctl update --rolling --batch 3", Some(3))]
+    fn rolling_update_options_are_correctly_parsed(#[case] command_line: &str, #[case] expected_batch: Option<usize>) {
+        let args: Vec<&str> = command_line.split(' ').collect();
+        let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
+
+        if let SubCommand::Update(ref uc) = settings.subcommand {
+            assert!(uc.rolling);
+            assert_eq!(uc.batch, expected_batch);
+        } else {
+            panic!("Command {:?} is wrong", settings.subcommand);
+        }
+    }
+
+    #[rstest]
+    #[case("clusterctl update --rolling --check")]
+    #[case("clusterctl update --batch 2")]
+    fn invalid_rolling_update_options_are_rejected(#[case] command_line: &str) {
+        let args: Vec<&str> = command_line.split(' ').collect();
+        assert!(ClusterSettings::try_parse_from(args).is_err());
+    }
+
+    #[rstest]
+    fn service_deploy_requires_a_service() {
+        let args = vec!["clusterctl", "service", "deploy"];
+        assert!(ClusterSettings::try_parse_from(args).is_err());
+    }
+
+    #[rstest]
+    fn invalid_service_values_are_rejected() {
+        let args = vec!["clusterctl", "service", "deploy", "kubernetes", "--set", "pod_network_cidr"];
+        assert!(ClusterSettings::try_parse_from(args).is_err());
+    }
+
+    #[rstest]
+    fn check_requires_dry_run() {
+        let args = vec!["clusterctl", "--check", "service", "deploy", "docker"];
+        assert!(ClusterSettings::try_parse_from(args).is_err());
+    }
+
+    #[rstest]
+    fn unknown_output_format_is_rejected() {
+        let args = vec!["clusterctl", "--output", "xml", "ping"];
+        assert!(ClusterSettings::try_parse_from(args).is_err());
+    }
+
     #[rstest]
     #[case("clusterctl --inventory /tmp/inventory.yaml ping", SubCommand::Ping(GenericCommand))]
-    #[case("clusterctl --inventory /tmp/inventory.yaml update", SubCommand::Update(GenericCommand))]
-    #[case("clusterctl --inventory /tmp/inventory.yaml reboot", SubCommand::Reboot(GenericCommand))]
-    #[case("clusterctl --inventory /tmp/inventory.yaml reboot", SubCommand::Shutdown(GenericCommand))]
-    #[case("clusterctl --inventory /tmp/inventory.yaml ssh", SubCommand::Ssh(GenericCommand))]
+    #[case("clusterctl --inventory /tmp/inventory.yaml update", SubCommand::Update(UpdateCommand { check: false, exclude: Vec::new(), rolling: false, batch: None }))]
+    #[case("clusterctl --inventory /tmp/inventory.yaml reboot", SubCommand::Reboot(RebootCommand { force: false }))]
+    #[case("clusterctl --inventory /tmp/inventory.yaml reboot", SubCommand::Shutdown(ShutdownCommand { force: false }))]
+    #[case("clusterctl --inventory /tmp/inventory.yaml ssh", SubCommand::Ssh(SshCommand { host: None }))]
     #[case("clusterctl --inventory /tmp/inventory.yaml uptime", SubCommand::Uptime(GenericCommand))]
     fn command_and_options_are_correctly_parsed(
         #[case] command_line: String,
@@ -159,6 +574,22 @@ mod tests {
         assert!(matches!(settings.subcommand, expected_subcommand));
     }
 
+    #[rstest]
+    #[case("clusterctl ssh", None)]
+    #[case("clusterctl ssh worker1", Some("worker1".to_string()))]
+    fn ssh_command_is_correctly_parsed(
+        #[case] command_line: &str,
+        #[case] expected_host: Option<String>) {
+        let args: Vec<&str> = command_line.split(' ').collect();
+        let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
+
+        if let SubCommand::Ssh(ref sc) = settings.subcommand {
+            assert_eq!(sc.host, expected_host);
+        } else {
+            panic!("Command {:?} is wrong", settings.subcommand);
+        }
+    }
+
     #[rstest]
     #[case("clusterctl --inventory /tmp/inventory.yaml run ls --chdir /", "ls", Some("/".to_string()), false)]
     #[case("clusterctl --inventory /tmp/inventory.yaml run ls --needs-become --chdir /", "ls", Some("/".to_string()), true)]
@@ -205,11 +636,71 @@ mod tests {
     }
 
     #[rstest]
-    #[case("clusterctl --inventory /tmp/inventory.yaml service deploy kubernetes", "kubernetes")]
-    #[case("clusterctl --inventory /tmp/inventory.yaml service deploy docker", "docker")]
+    #[case("clusterctl --inventory /tmp/inventory.yaml inventory list", InventorySubCommand::List(InventoryCommandOptions))]
+    #[case("clusterctl --inventory /tmp/inventory.yaml inventory show", InventorySubCommand::Show(InventoryCommandOptions))]
+    #[case("clusterctl --inventory /tmp/inventory.yaml inventory validate", InventorySubCommand::Validate(InventoryCommandOptions))]
+    fn inventory_command_and_options_are_correctly_parsed(
+        #[case] command_line: &str,
+        #[case] expected_subcommand: InventorySubCommand) {
+        let args: Vec<&str> = command_line.split(' ').collect();
+        let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
+
+        assert_eq!(settings.inventory.unwrap(), INVENTORY_FILE);
+
+        if let SubCommand::Inventory(ref ic) = settings.subcommand {
+            assert_eq!(std::mem::discriminant(&ic.subcommand), std::mem::discriminant(&expected_subcommand));
+        } else {
+            panic!("Command {:?} is wrong", settings.subcommand);
+        }
+    }
+
+    #[rstest]
+    #[case("clusterctl --inventory /tmp/inventory.yaml inventory add-host worker3 --address 192.168.0.13", "worker3", "192.168.0.13", vec![])]
+    #[case("clusterctl --inventory /tmp/inventory.yaml inventory add-host nas -a 192.168.0.20 -g cluster -g storage", "nas", "192.168.0.20", vec!["cluster", "storage"])]
+    fn inventory_add_host_command_and_options_are_correctly_parsed(
+        #[case] command_line: &str,
+        #[case] expected_host: &str,
+        #[case] expected_address: &str,
+        #[case] expected_groups: Vec<&str>) {
+        let args: Vec<&str> = command_line.split(' ').collect();
+        let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
+
+        match settings.subcommand {
+            SubCommand::Inventory(InventoryCommand { subcommand: InventorySubCommand::AddHost(ref options) }) => {
+                assert_eq!(options.host, expected_host);
+                assert_eq!(options.address, expected_address);
+                assert_eq!(options.groups, expected_groups);
+            },
+            _ => panic!("Command {:?} is wrong", settings.subcommand)
+        };
+    }
+
+    #[rstest]
+    #[case("clusterctl --inventory /tmp/inventory.yaml inventory move-host worker1 --to managers", ClusterRole::Managers)]
+    #[case("clusterctl --inventory /tmp/inventory.yaml inventory move-host worker1 --to workers", ClusterRole::Workers)]
+    fn inventory_move_host_command_and_options_are_correctly_parsed(
+        #[case] command_line: &str,
+        #[case] expected_role: ClusterRole) {
+        let args: Vec<&str> = command_line.split(' ').collect();
+        let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
+
+        match settings.subcommand {
+            SubCommand::Inventory(InventoryCommand { subcommand: InventorySubCommand::MoveHost(ref options) }) => {
+                assert_eq!(options.host, "worker1");
+                assert_eq!(options.to, expected_role);
+            },
+            _ => panic!("Command {:?} is wrong", settings.subcommand)
+        };
+    }
+
+    #[rstest]
+    #[case("clusterctl --inventory /tmp/inventory.yaml service deploy kubernetes", vec!["kubernetes"], false)]
+    #[case("clusterctl --inventory /tmp/inventory.yaml service deploy docker", vec!["docker"], false)]
+    #[case("clusterctl --inventory /tmp/inventory.yaml service deploy podman kubernetes --redeploy", vec!["podman", "kubernetes"], true)]
     fn service_deploy_command_and_options_are_correctly_parsed(
         #[case] command_line: &str,
-        #[case] expected_service_name: &str) {
+        #[case] expected_services: Vec<&str>,
+        #[case] expected_redeploy: bool) {
         let args: Vec<&str> = command_line.split(' ').collect();
         let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
 
@@ -217,7 +708,8 @@ mod tests {
 
         if let SubCommand::Service(ref sc) = settings.subcommand {
             if let ServiceSubCommand::Deploy(ref ssc) = sc.subcommand {
-                assert_eq!(ssc.service, expected_service_name);
+                assert_eq!(ssc.services, expected_services);
+                assert_eq!(ssc.redeploy, expected_redeploy);
             } else {
                 panic!("Subcommand {:?} is wrong", sc.subcommand);
             }
@@ -227,11 +719,13 @@ mod tests {
     }
 
     #[rstest]
-    #[case("clusterctl --inventory /tmp/inventory.yaml service delete kubernetes", "kubernetes")]
-    #[case("clusterctl --inventory /tmp/inventory.yaml service delete docker", "docker")]
+    #[case("clusterctl --inventory /tmp/inventory.yaml service delete kubernetes", "kubernetes", false)]
+    #[case("clusterctl --inventory /tmp/inventory.yaml service delete docker", "docker", false)]
+    #[case("clusterctl --inventory /tmp/inventory.yaml service delete kubernetes --with-dependencies", "kubernetes", true)]
     fn service_delete_command_and_options_are_correctly_parsed(
         #[case] command_line: &str,
-        #[case] expected_service_name: &str) {
+        #[case] expected_service_name: &str,
+        #[case] expected_with_dependencies: bool) {
         let args: Vec<&str> = command_line.split(' ').collect();
         let settings: ClusterSettings = ClusterSettings::try_parse_from(args).unwrap();
 
@@ -239,7 +733,8 @@ mod tests {
 
         if let SubCommand::Service(ref sc) = settings.subcommand {
             if let ServiceSubCommand::Delete(ref ssc) = sc.subcommand {
-                assert_eq!(ssc.service, expected_service_name);
+                assert_eq!(ssc.service_options.service, expected_service_name);
+                assert_eq!(ssc.with_dependencies, expected_with_dependencies);
             } else {
                 panic!("Subcommand {:?} is wrong", sc.subcommand);
             }
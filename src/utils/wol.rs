@@ -0,0 +1,100 @@
+/*
+ * CLI to manage a cluster of machines.
+ *
+ * Copyright (C) 2020-2021 Rodrigo Moya <rodrigo@gnome.org>
+ */
+
+use std::io::{Error, ErrorKind};
+use std::net::{SocketAddr, UdpSocket};
+
+use log::error;
+
+/// A MAC address, as the 6 bytes identifying a network card.
+pub type MacAddress = [u8; 6];
+
+/// Parses a MAC address written as 6 hexadecimal bytes separated by `:` or
+/// `-`, like `b8:27:eb:12:34:56`. The same separator must be used all along.
+pub fn parse_mac_address(text: &str) -> Result<MacAddress, Error> {
+    let text = text.trim();
+    let separator = if text.contains(':') { ':' } else { '-' };
+    let bytes: Vec<u8> = text
+        .split(separator)
+        .filter(|b| b.len() == 2 && b.chars().all(|c| c.is_ascii_hexdigit()))
+        .filter_map(|b| u8::from_str_radix(b, 16).ok())
+        .collect();
+
+    if bytes.len() != 6 || text.len() != 17 {
+        let msg = format!("Invalid MAC address '{}'", text);
+        error!("{}", msg);
+        return Err(Error::new(ErrorKind::InvalidData, msg));
+    }
+
+    let mut mac = [0; 6];
+    mac.copy_from_slice(&bytes);
+    Ok(mac)
+}
+
+/// Builds the Wake-on-LAN magic packet for a network card: 6 bytes set to
+/// 0xff, followed by the MAC address repeated 16 times.
+pub fn magic_packet(mac: &MacAddress) -> Vec<u8> {
+    let mut packet = vec![0xff; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(mac);
+    }
+    packet
+}
+
+/// Sends the magic packet waking up the machine with the `mac` network card
+/// to `address`, which is usually the broadcast address of its network.
+pub fn send(mac: &MacAddress, address: SocketAddr) -> Result<(), Error> {
+    let socket = UdpSocket::bind(if address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+    if address.is_ipv4() {
+        socket.set_broadcast(true)?;
+    }
+
+    socket.send_to(&magic_packet(mac), address)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::time::Duration;
+    use rstest::rstest;
+    use super::{magic_packet, parse_mac_address, send};
+
+    #[rstest]
+    #[case("b8:27:eb:12:34:56", [0xb8, 0x27, 0xeb, 0x12, 0x34, 0x56])]
+    #[case("DC-A6-32-AB-CD-EF", [0xdc, 0xa6, 0x32, 0xab, 0xcd, 0xef])]
+    fn mac_addresses_are_parsed(#[case] text: &str, #[case] expected: [u8; 6]) {
+        assert_eq!(parse_mac_address(text).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("b8:27:eb:12:34")]
+    #[case("b8:27:eb:12:34:56:78")]
+    #[case("b8:27:eb:12:34:zz")]
+    #[case("b827eb123456")]
+    #[case("b8:27-eb:12:34:56")]
+    #[case("b8-27-eb-12-34:56")]
+    #[case("+8:27:eb:12:34:56")]
+    fn invalid_mac_addresses_are_rejected(#[case] text: &str) {
+        assert!(parse_mac_address(text).is_err());
+    }
+
+    #[rstest]
+    fn magic_packet_is_sent() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mac = parse_mac_address("b8:27:eb:12:34:56").unwrap();
+
+        send(&mac, listener.local_addr().unwrap()).unwrap();
+
+        let mut buffer = [0; 256];
+        let (size, _) = listener.recv_from(&mut buffer).unwrap();
+        assert_eq!(size, 102);
+        assert_eq!(&buffer[..size], magic_packet(&mac).as_slice());
+        assert_eq!(&buffer[..6], &[0xff; 6]);
+        assert!(buffer[6..size].chunks(6).all(|c| c == mac));
+    }
+}